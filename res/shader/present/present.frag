@@ -0,0 +1,29 @@
+#version 330 core
+
+out vec4 FragColor;
+in vec2 uv;
+
+uniform sampler2D scene;
+uniform float exposure;
+// 0 = clamp, 1 = reinhard, 2 = aces
+uniform int tonemap;
+
+vec3 aces(vec3 x) {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+vec3 linear_to_srgb(vec3 color) {
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, vec3(lessThanEqual(color, vec3(0.0031308))));
+}
+
+void main() {
+    vec3 color = texture(scene, uv).rgb * exposure;
+    if (tonemap == 1) {
+        color = color / (color + 1.0);
+    } else if (tonemap == 2) {
+        color = aces(color);
+    }
+    FragColor = vec4(linear_to_srgb(clamp(color, 0.0, 1.0)), 1.0);
+}
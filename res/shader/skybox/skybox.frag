@@ -0,0 +1,14 @@
+#version 330 core
+
+out vec4 FragColor;
+// nothing to pick in the sky
+out uint ObjectId;
+in vec3 direction;
+
+uniform samplerCube sky;
+uniform float intensity;
+
+void main() {
+    FragColor = vec4(texture(sky, normalize(direction)).rgb * intensity, 1.0);
+    ObjectId = 0u;
+}
@@ -0,0 +1,13 @@
+#version 330 core
+
+uniform mat4 inverseViewProjection;
+
+out vec3 direction;
+
+// fullscreen triangle on the far plane, the direction is unprojected per corner
+void main() {
+    vec2 position = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2) * 2.0 - 1.0;
+    vec4 world = inverseViewProjection * vec4(position, 1.0, 1.0);
+    direction = world.xyz / world.w;
+    gl_Position = vec4(position, 1.0, 1.0);
+}
@@ -1,21 +1,200 @@
 use std::mem;
 
-use gl::types::{GLenum, GLfloat, GLint};
+use gl::types::{GLboolean, GLenum, GLfloat, GLint, GLuint};
+use glam::{Vec2, Vec4};
 
 use crate::alignment::Attribute::*;
+use crate::error::{Error, Result};
 
+/// Not every component and attribute has a vertex type in the viewer yet.
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Component {
+    Float,
+    Half,
+    Byte,
+    UByte,
+    Short,
+    UShort,
+    Int,
+    UInt,
+    /// `INT_2_10_10_10_REV`, always 4 components packed into 4 bytes
+    Packed2101010,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+    /// converted to float as is
+    Float,
+    /// integers mapped to 0..1 or -1..1
+    Normalized,
+    /// stays an integer in the shader, uploaded with `glVertexAttribIPointer`
+    Integer,
+}
+
+/// The location of an attribute is its index in the layout, unless it is a `Custom` one.
+#[allow(dead_code)]
 pub enum Attribute {
     Color,
     Color4,
     Position,
     Normal,
     UV,
+    /// xyz tangent and the handedness of the bitangent in w
+    Tangent,
+    /// rgba as normalized u8
+    ColorU8,
+    /// uv as normalized u16, only for coordinates inside of 0..1
+    UVU16,
+    /// uv as half floats, see `pack_half2`
+    UVHalf,
+    /// xyz normal with a 2 bit w, see `pack_snorm_2_10_10_10`
+    PackedNormal,
+    /// four u8 joint indices
+    BoneIndices,
+    /// four normalized u8 joint weights
+    BoneWeights,
+    Custom {
+        name: &'static str,
+        location: u32,
+        components: u8,
+        component: Component,
+        mode: Mode,
+    },
+}
+
+impl Component {
+    pub fn gl_type(&self) -> GLenum {
+        match self {
+            Component::Float => gl::FLOAT,
+            Component::Half => gl::HALF_FLOAT,
+            Component::Byte => gl::BYTE,
+            Component::UByte => gl::UNSIGNED_BYTE,
+            Component::Short => gl::SHORT,
+            Component::UShort => gl::UNSIGNED_SHORT,
+            Component::Int => gl::INT,
+            Component::UInt => gl::UNSIGNED_INT,
+            Component::Packed2101010 => gl::INT_2_10_10_10_REV,
+        }
+    }
+
+    pub const fn bytes(&self) -> u32 {
+        match self {
+            Component::Float => mem::size_of::<GLfloat>() as u32,
+            Component::Half => 2,
+            Component::Byte => 1,
+            Component::UByte => 1,
+            Component::Short => 2,
+            Component::UShort => 2,
+            Component::Int => mem::size_of::<GLint>() as u32,
+            Component::UInt => mem::size_of::<GLuint>() as u32,
+            // one component holds all 4 values
+            Component::Packed2101010 => 1,
+        }
+    }
+}
+
+/// Rounds to the nearest half float like `packHalf2x16`, too large values become infinity.
+pub fn pack_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        // infinity stays infinity and nan stays nan
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        // subnormal, the implicit leading 1 has to be shifted in as well
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let rounded = (mantissa + (1 << (shift - 1)) - 1 + ((mantissa >> shift) & 1)) >> shift;
+        return sign | rounded as u16;
+    }
+    // round half to even, a carry out of the mantissa correctly bumps the exponent
+    let rounded = (mantissa + 0xfff + ((mantissa >> 13) & 1)) >> 13;
+    sign | (((exponent as u32) << 10) + rounded) as u16
+}
+
+pub fn pack_half2(value: Vec2) -> [u16; 2] {
+    [pack_half(value.x), pack_half(value.y)]
+}
+
+/// Packs a vector with components in -1..1 for a normalized `INT_2_10_10_10_REV` attribute,
+/// x in the lowest bits. w only has 2 bits, so it can just hold -1, 0 or 1.
+pub fn pack_snorm_2_10_10_10(value: Vec4) -> u32 {
+    let pack = |x: f32, max: f32, bits: u32| {
+        let x = (x.clamp(-1.0, 1.0) * max).round() as i32;
+        (x as u32) & ((1 << bits) - 1)
+    };
+    pack(value.x, 511.0, 10) |
+        pack(value.y, 511.0, 10) << 10 |
+        pack(value.z, 511.0, 10) << 20 |
+        pack(value.w, 1.0, 2) << 30
 }
 
 pub struct Layout {
     pub attributes: Vec<Attribute>,
 }
 
+/// A `#[repr(C)]` vertex struct that knows its own attribute layout.
+/// Use `vertex_format!` to declare the struct and its layout in one place.
+pub trait VertexFormat: Sized {
+    fn layout() -> Layout;
+}
+
+/// Declares a `#[repr(C)]` vertex struct and implements `VertexFormat` for it,
+/// each field is followed by the attribute it is uploaded as.
+/// ```ignore
+/// vertex_format! {
+///     pub struct Vertex {
+///         pub position: Vec3 => Position,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! vertex_format {
+    ($(#[$meta:meta])* $vis:vis struct $name:ident {
+        $($(#[$field_meta:meta])* $field_vis:vis $field:ident: $ty:ty => $attribute:expr),* $(,)?
+    }) => {
+        $(#[$meta])*
+        #[repr(C)]
+        $vis struct $name {
+            $($(#[$field_meta])* $field_vis $field: $ty),*
+        }
+
+        impl $crate::alignment::VertexFormat for $name {
+            fn layout() -> $crate::alignment::Layout {
+                $crate::alignment::Layout::new(vec![$($attribute),*])
+            }
+        }
+
+        // every field has to be exactly as big as its attribute and sit where the layout
+        // puts it, otherwise the vertex pointers read garbage
+        const _: () = {
+            let mut offset = 0usize;
+            $(
+                assert!(::core::mem::size_of::<$ty>() == ($attribute).size_bytes() as usize,
+                        concat!("size of ", stringify!($name), "::", stringify!($field),
+                                " does not match its attribute"));
+                assert!(::core::mem::offset_of!($name, $field) == offset,
+                        concat!(stringify!($name), "::", stringify!($field),
+                                " is not at its layout offset"));
+                offset += ($attribute).size_bytes() as usize;
+            )*
+            assert!(::core::mem::size_of::<$name>() == offset,
+                    concat!(stringify!($name), " has padding the layout does not know about"));
+        };
+    };
+}
+
 
 impl Layout {
     pub fn new(attributes: Vec<Attribute>) -> Layout {
@@ -30,42 +209,219 @@ impl Layout {
         }
         sum
     }
+
+    /// Attributes without an explicit location use their index in the layout.
+    pub fn location(&self, index: usize) -> u32 {
+        self.attributes[index].explicit_location().unwrap_or(index as u32)
+    }
+
+    pub fn at_location(&self, location: u32) -> Option<&Attribute> {
+        (0..self.attributes.len()).find(|x| self.location(*x) == location)
+                                  .map(|x| &self.attributes[x])
+    }
+
+    /// A `Custom` location can collide with the index of another attribute,
+    /// gl would silently let the later pointer win.
+    pub fn check_locations(&self) -> Result<()> {
+        for i in 0..self.attributes.len() {
+            let location = self.location(i);
+            if let Some(j) = (0..i).find(|x| self.location(*x) == location) {
+                return Err(Error::LayoutMismatch(format!(
+                    "{} and {} are both at location {}", self.attributes[j].name(),
+                    self.attributes[i].name(), location)));
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Attribute {
-    pub fn size(&self) -> u8 {
+    pub const fn size(&self) -> u8 {
         match self {
             Color => 3,
             Color4 => 4,
             Position => 3,
             Normal => 3,
             UV => 2,
+            Tangent => 4,
+            ColorU8 => 4,
+            UVU16 => 2,
+            UVHalf => 2,
+            PackedNormal => 4,
+            BoneIndices => 4,
+            BoneWeights => 4,
+            Custom { components, .. } => *components,
         }
     }
-    pub fn size_bytes(&self) -> u32 {
+
+    pub const fn component(&self) -> Component {
         match self {
-            Color => 3 * Attribute::float_bytes(),
-            Color4 => 4 * Attribute::float_bytes(),
-            Position => 3 * Attribute::float_bytes(),
-            Normal => 3 * Attribute::float_bytes(),
-            UV => 2 * Attribute::float_bytes(),
+            Color | Color4 | Position | Normal | UV | Tangent => Component::Float,
+            ColorU8 | BoneIndices | BoneWeights => Component::UByte,
+            UVU16 => Component::UShort,
+            UVHalf => Component::Half,
+            PackedNormal => Component::Packed2101010,
+            Custom { component, .. } => *component,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        match self {
+            Color | Color4 | Position | Normal | UV | Tangent | UVHalf => Mode::Float,
+            ColorU8 | UVU16 | PackedNormal | BoneWeights => Mode::Normalized,
+            BoneIndices => Mode::Integer,
+            Custom { mode, .. } => *mode,
+        }
+    }
+
+    pub const fn size_bytes(&self) -> u32 {
+        match self.component() {
+            Component::Packed2101010 => mem::size_of::<GLuint>() as u32,
+            component => self.size() as u32 * component.bytes(),
         }
     }
+
     pub fn gl_type(&self) -> GLenum {
+        self.component().gl_type()
+    }
+
+    pub fn normalized(&self) -> GLboolean {
+        if self.mode() == Mode::Normalized { gl::TRUE } else { gl::FALSE }
+    }
+
+    pub fn explicit_location(&self) -> Option<u32> {
         match self {
-            Color => gl::FLOAT,
-            Color4 => gl::FLOAT,
-            Position => gl::FLOAT,
-            Normal => gl::FLOAT,
-            UV => gl::FLOAT,
+            Custom { location, .. } => Some(*location),
+            _ => None,
         }
     }
 
-    pub fn float_bytes() -> u32 {
-        mem::size_of::<GLfloat>() as u32
+    pub fn name(&self) -> &'static str {
+        match self {
+            Color => "Color",
+            Color4 => "Color4",
+            Position => "Position",
+            Normal => "Normal",
+            UV => "UV",
+            Tangent => "Tangent",
+            ColorU8 => "ColorU8",
+            UVU16 => "UVU16",
+            UVHalf => "UVHalf",
+            PackedNormal => "PackedNormal",
+            BoneIndices => "BoneIndices",
+            BoneWeights => "BoneWeights",
+            Custom { name, .. } => name,
+        }
     }
+
     pub fn int_bytes() -> u32 {
-        mem::size_of::<GLfloat>() as u32
+        mem::size_of::<GLuint>() as u32
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_floats() {
+        assert_eq!(pack_half(0.0), 0x0000);
+        assert_eq!(pack_half(-0.0), 0x8000);
+        assert_eq!(pack_half(1.0), 0x3c00);
+        assert_eq!(pack_half(-2.0), 0xc000);
+        assert_eq!(pack_half(0.5), 0x3800);
+        // 0.1 is not exact, the nearest half is 0x2e66
+        assert_eq!(pack_half(0.1), 0x2e66);
+        assert_eq!(pack_half(65504.0), 0x7bff);
+        assert_eq!(pack_half(65520.0), 0x7c00);
+        assert_eq!(pack_half(f32::INFINITY), 0x7c00);
+        assert_eq!(pack_half(f32::NEG_INFINITY), 0xfc00);
+        assert_eq!(pack_half(f32::NAN) & 0x7c00, 0x7c00);
+        assert_ne!(pack_half(f32::NAN) & 0x3ff, 0);
+    }
+
+    #[test]
+    fn half_float_subnormals() {
+        // the smallest subnormal is 2^-24, half of it rounds to even, so to 0
+        assert_eq!(pack_half(2f32.powi(-24)), 0x0001);
+        assert_eq!(pack_half(2f32.powi(-25)), 0x0000);
+        assert_eq!(pack_half(3.0 * 2f32.powi(-25)), 0x0002);
+        assert_eq!(pack_half(2f32.powi(-15)), 0x0200);
+        assert_eq!(pack_half(1e-10), 0x0000);
+    }
+
+    #[test]
+    fn half_float_rounding() {
+        // 1 + 2^-11 is exactly between 1 and the next half, ties go to the even 1
+        assert_eq!(pack_half(1.0 + 2f32.powi(-11)), 0x3c00);
+        assert_eq!(pack_half(1.0 + 3.0 * 2f32.powi(-11)), 0x3c02);
+        // rounding up the largest mantissa carries into the exponent
+        assert_eq!(pack_half(2.0 - 2f32.powi(-12)), 0x4000);
+        assert_eq!(pack_half2(Vec2::new(1.0, -2.0)), [0x3c00, 0xc000]);
+    }
+
+    #[test]
+    fn packed_2_10_10_10() {
+        assert_eq!(pack_snorm_2_10_10_10(Vec4::ZERO), 0);
+        assert_eq!(pack_snorm_2_10_10_10(Vec4::new(1.0, 0.0, 0.0, 0.0)), 511);
+        assert_eq!(pack_snorm_2_10_10_10(Vec4::new(-1.0, 0.0, 0.0, 0.0)), 0x201);
+        assert_eq!(pack_snorm_2_10_10_10(Vec4::new(0.0, 1.0, 0.0, 0.0)), 511 << 10);
+        assert_eq!(pack_snorm_2_10_10_10(Vec4::new(0.0, 0.0, 1.0, 0.0)), 511 << 20);
+        assert_eq!(pack_snorm_2_10_10_10(Vec4::new(0.0, 0.0, 0.0, 1.0)), 1 << 30);
+        assert_eq!(pack_snorm_2_10_10_10(Vec4::new(0.0, 0.0, 0.0, -1.0)), 3 << 30);
+        // out of range values are clamped instead of wrapping into the next component
+        assert_eq!(pack_snorm_2_10_10_10(Vec4::new(4.0, 0.0, 0.0, 0.0)), 511);
+    }
+
+    vertex_format! {
+        struct SkinnedVertex {
+            position: [f32; 3] => Position,
+            color: [u8; 4] => ColorU8,
+            uv: [u16; 2] => UVU16,
+            bones: [u8; 4] => BoneIndices,
+            weights: [u8; 4] => BoneWeights,
+            material: u32 => Custom {
+                name: "Material",
+                location: 7,
+                components: 1,
+                component: Component::UInt,
+                mode: Mode::Integer,
+            },
+        }
+    }
+
+    #[test]
+    fn integer_normalized_and_custom_attributes() {
+        let layout = SkinnedVertex::layout();
+        assert_eq!(layout.vertex_size(), 12 + 4 + 4 + 4 + 4 + 4);
+        assert_eq!((0..6).map(|x| layout.location(x)).collect::<Vec<_>>(), [0, 1, 2, 3, 4, 7]);
+        assert_eq!(layout.at_location(7).map(|x| x.name()), Some("Material"));
+        assert!(layout.at_location(5).is_none());
+        assert!(layout.check_locations().is_ok());
+
+        let attributes = &layout.attributes;
+        assert_eq!(attributes[1].gl_type(), gl::UNSIGNED_BYTE);
+        assert_eq!(attributes[1].normalized(), gl::TRUE);
+        assert_eq!(attributes[2].gl_type(), gl::UNSIGNED_SHORT);
+        assert_eq!(attributes[2].normalized(), gl::TRUE);
+        assert_eq!(attributes[3].mode(), Mode::Integer);
+        assert_eq!(attributes[3].normalized(), gl::FALSE);
+        assert_eq!(attributes[4].mode(), Mode::Normalized);
+        assert_eq!(attributes[5].gl_type(), gl::UNSIGNED_INT);
+        assert_eq!(attributes[5].mode(), Mode::Integer);
+    }
+
+    #[test]
+    fn colliding_locations() {
+        let custom = Custom {
+            name: "Weight",
+            location: 1,
+            components: 1,
+            component: Component::Float,
+            mode: Mode::Float,
+        };
+        let layout = Layout::new(vec![Position, Normal, custom]);
+        assert!(matches!(layout.check_locations(), Err(Error::LayoutMismatch(_))));
+        assert!(Layout::new(vec![Position, Normal, Color4]).check_locations().is_ok());
+    }
+}
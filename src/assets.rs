@@ -0,0 +1,204 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
+
+use image::DynamicImage;
+
+use crate::alignment::VertexFormat;
+use crate::error::{Error, Result};
+use crate::model::{load_obj, LitVertex, Materials, Model, TexVertex, Vertex};
+use crate::textures::{ColorSpace, Texture2D, TextureCube};
+
+/// A vertex type a `Model` can be built from an obj file with.
+pub trait ModelAsset: VertexFormat + Sized + 'static {
+    fn from_obj(models: Vec<tobj::Model>, materials: Materials, path: &Path,
+                cache: &mut TextureCache) -> Result<Model<Self>>;
+}
+
+impl ModelAsset for Vertex {
+    fn from_obj(models: Vec<tobj::Model>, _materials: Materials, _path: &Path,
+                _cache: &mut TextureCache) -> Result<Model<Vertex>> {
+        Model::from_tobj(models)
+    }
+}
+
+impl ModelAsset for TexVertex {
+    fn from_obj(models: Vec<tobj::Model>, materials: Materials, path: &Path,
+                cache: &mut TextureCache) -> Result<Model<TexVertex>> {
+        let materials = materials_or_error(materials, path)?;
+        Model::from_textured_tobj(models, materials, directory(path), cache)
+    }
+}
+
+impl ModelAsset for LitVertex {
+    fn from_obj(models: Vec<tobj::Model>, materials: Materials, path: &Path,
+                cache: &mut TextureCache) -> Result<Model<LitVertex>> {
+        // untextured models like the teapot come without a mtl file, a broken one is still an error
+        let materials = match materials {
+            Err(tobj::LoadError::OpenFileFailed) => Vec::new(),
+            materials => materials_or_error(materials, path)?,
+        };
+        Model::from_lit_tobj(models, materials, directory(path), cache, 45.0)
+    }
+}
+
+fn directory(path: &Path) -> &Path {
+    path.parent().unwrap_or_else(|| Path::new("."))
+}
+
+fn materials_or_error(materials: Materials, path: &Path) -> Result<Vec<tobj::Material>> {
+    materials.map_err(|e| Error::ObjParse {
+        path: path.to_path_buf(),
+        source: e,
+    })
+}
+
+/// Loads models by path and keeps them, so every object using the same file
+/// with the same vertex type shares one set of gpu buffers.
+pub struct AssetManager {
+    models: HashMap<(PathBuf, TypeId), Rc<dyn Any>>,
+    pub textures: TextureCache,
+}
+
+impl AssetManager {
+    pub fn new() -> AssetManager {
+        AssetManager {
+            models: HashMap::new(),
+            textures: TextureCache::new(),
+        }
+    }
+
+    pub fn model<V: ModelAsset>(&mut self, path: &Path) -> Result<Rc<Model<V>>> {
+        let canonical = fs::canonicalize(path).map_err(|e| Error::io(path, e))?;
+        let key = (canonical, TypeId::of::<V>());
+        if let Some(model) = self.models.get(&key) {
+            return Ok(model.clone().downcast::<Model<V>>().unwrap());
+        }
+
+        let (models, materials) = load_obj(path)?;
+        let model = Rc::new(V::from_obj(models, materials, path, &mut self.textures)?);
+        self.models.insert(key, model.clone());
+        Ok(model)
+    }
+}
+
+const FALLBACK_TEXTURE: &str = "res/textures/uvs.png";
+
+/// Shares textures between materials, keyed by their canonical path.
+/// Only weak references are kept, so the gl texture is deleted
+/// as soon as the last material using it is dropped.
+pub struct TextureCache {
+    textures: HashMap<(PathBuf, ColorSpace), Weak<Texture2D>>,
+    fallback: HashMap<ColorSpace, Weak<Texture2D>>,
+    /// textures materials asked for that don't exist, shown in the settings
+    missing: Vec<PathBuf>,
+}
+
+impl TextureCache {
+    pub fn new() -> TextureCache {
+        TextureCache {
+            textures: HashMap::new(),
+            fallback: HashMap::new(),
+            missing: Vec::new(),
+        }
+    }
+
+    /// The same file used as color and as data is loaded twice, since the formats differ.
+    pub fn load(&mut self, path: &Path, color_space: ColorSpace) -> Result<Rc<Texture2D>> {
+        let canonical = fs::canonicalize(path).map_err(|e| Error::io(path, e))?;
+        let key = (canonical, color_space);
+        if let Some(texture) = self.textures.get(&key).and_then(|x| x.upgrade()) {
+            return Ok(texture);
+        }
+
+        let img = open_image(path)?;
+        let texture = Rc::new(Texture2D::from_image(&img.flipv(), color_space));
+        self.textures.insert(key, Rc::downgrade(&texture));
+        Ok(texture)
+    }
+
+    /// `res/textures/uvs.png`, or a generated checkerboard if that is missing too
+    pub fn fallback(&mut self, color_space: ColorSpace) -> Rc<Texture2D> {
+        if let Some(texture) = self.fallback.get(&color_space).and_then(|x| x.upgrade()) {
+            return texture;
+        }
+        let texture = match self.load(Path::new(FALLBACK_TEXTURE), color_space) {
+            Ok(texture) => texture,
+            Err(_) => Rc::new(Texture2D::checkerboard(64, 8, color_space)),
+        };
+        self.fallback.insert(color_space, Rc::downgrade(&texture));
+        texture
+    }
+
+    /// The fallback for a texture that does not exist, remembering what was missing.
+    pub fn fallback_for(&mut self, path: PathBuf, color_space: ColorSpace) -> Rc<Texture2D> {
+        if !self.missing.contains(&path) {
+            self.missing.push(path);
+        }
+        self.fallback(color_space)
+    }
+
+    pub fn missing(&self) -> &[PathBuf] {
+        &self.missing
+    }
+
+    /// Number of textures that are still alive
+    pub fn alive(&self) -> usize {
+        self.textures.values().filter(|x| x.strong_count() > 0).count()
+    }
+}
+
+fn open_image(path: &Path) -> Result<DynamicImage> {
+    image::open(path).map_err(|e| Error::ImageDecode {
+        path: path.to_path_buf(),
+        source: e,
+    })
+}
+
+/// Six face images in gl order: +x, -x, +y, -y, +z, -z.
+pub fn load_cubemap(faces: [&Path; 6], color_space: ColorSpace) -> Result<TextureCube> {
+    let [px, nx, py, ny, pz, nz] = faces;
+    let images = [open_image(px)?, open_image(nx)?, open_image(py)?,
+                  open_image(ny)?, open_image(pz)?, open_image(nz)?];
+    TextureCube::from_images(&images, color_space)
+}
+
+/// A latitude/longitude panorama like an `.hdr` environment, `size` is the width of a face.
+pub fn load_equirectangular(path: &Path, size: u32, color_space: ColorSpace) -> Result<TextureCube> {
+    let image = open_image(path)?;
+    Ok(TextureCube::from_equirectangular(&image, size, color_space))
+}
+
+/// What an image file most likely holds: float formats like `.hdr` and `.exr` are linear,
+/// 8 and 16 bit formats are srgb.
+pub fn default_color_space(path: &Path) -> ColorSpace {
+    let extension = path.extension().and_then(|x| x.to_str()).unwrap_or("");
+    if extension.eq_ignore_ascii_case("hdr") || extension.eq_ignore_ascii_case("exr") {
+        ColorSpace::Linear
+    } else {
+        ColorSpace::Srgb
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::HeadlessContext;
+
+    #[test]
+    fn same_path_shares_the_model() {
+        let _context = HeadlessContext::new()
+            .unwrap_or_else(|e| panic!("needs a surfaceless EGL context: {}", e));
+        let mut assets = AssetManager::new();
+        let first = assets.model::<LitVertex>(Path::new("res/model/teapot.obj")).unwrap();
+        let second = assets.model::<LitVertex>(Path::new("res/model/../model/teapot.obj")).unwrap();
+        assert!(Rc::ptr_eq(&first, &second));
+
+        // another vertex type needs its own buffers
+        let flat = assets.model::<Vertex>(Path::new("res/model/teapot.obj")).unwrap();
+        assert_eq!(Rc::strong_count(&first), 3);
+        assert_eq!(flat.meshes.len(), first.meshes.len());
+    }
+}
@@ -1,15 +1,20 @@
 use std::collections::HashMap;
+use std::io;
 use std::os::raw::c_void;
+use std::path::Path;
 
 use gl::types::GLenum;
+use image::{imageops, ImageError, Rgba, RgbaImage};
 
+use crate::error::{Error, Result};
 use crate::Texture2D;
+use crate::textures::TextureFormat;
 use crate::textures::State;
 
 pub struct FrameBuffer {
-    pub id: u32,
-    pub width: u32,
-    pub height: u32,
+    id: u32,
+    width: u32,
+    height: u32,
 }
 
 impl FrameBuffer {
@@ -17,21 +22,20 @@ impl FrameBuffer {
         unsafe {
             let mut id = 0;
             gl::GenFramebuffers(1, &mut id);
-            let buffer = FrameBuffer {
+            FrameBuffer {
                 id,
                 width,
                 height,
-            };
-            buffer
+            }
         }
     }
     pub fn attach_texture(&self, texture: &Texture2D, target: GLenum) {
-        if texture.width != self.width || texture.height != self.height {
+        if texture.width() != self.width || texture.height() != self.height {
             panic!("Texture coordinates are not the same as Framebuffer");
         }
         unsafe {
             gl::FramebufferTexture2D(gl::FRAMEBUFFER, target, gl::TEXTURE_2D,
-                                     texture.id, 0);
+                                     texture.id(), 0);
         }
     }
 
@@ -44,12 +48,22 @@ impl FrameBuffer {
         }
     }
 
-    pub fn assert_status(&self) {
+    pub fn check_status(&self) -> Result<()> {
         unsafe {
-            if gl::CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
-                panic!("Framebuffer is not complete!");
+            let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                return Err(Error::IncompleteFramebuffer { status });
             }
         }
+        Ok(())
+    }
+}
+
+impl Drop for FrameBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.id);
+        }
     }
 }
 
@@ -67,9 +81,9 @@ impl State for FrameBuffer {
 }
 
 pub struct RenderBuffer {
-    pub id: u32,
-    pub width: u32,
-    pub height: u32,
+    id: u32,
+    width: u32,
+    height: u32,
 }
 
 impl RenderBuffer {
@@ -77,13 +91,11 @@ impl RenderBuffer {
         unsafe {
             let mut id = 0;
             gl::GenRenderbuffers(1, &mut id);
-            let buffer = RenderBuffer {
+            RenderBuffer {
                 id,
                 width,
                 height,
-            };
-
-            buffer
+            }
         }
     }
 
@@ -95,6 +107,14 @@ impl RenderBuffer {
     }
 }
 
+impl Drop for RenderBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteRenderbuffers(1, &self.id);
+        }
+    }
+}
+
 impl State for RenderBuffer {
     fn bind(&self) {
         unsafe {
@@ -109,9 +129,17 @@ impl State for RenderBuffer {
     }
 }
 
+/// How an attachment was created, so it can be recreated at another size.
+#[derive(Clone, Copy)]
+enum AttachmentFormat {
+    Texture(TextureFormat),
+    Buffer(GLenum),
+}
+
 pub struct RenderTarget {
     frame_buffer: FrameBuffer,
     attachments: HashMap<u32, RenderAttachment>,
+    formats: HashMap<u32, AttachmentFormat>,
 }
 
 impl RenderTarget {
@@ -119,13 +147,56 @@ impl RenderTarget {
         RenderTarget {
             frame_buffer: FrameBuffer::new(width, height),
             attachments: HashMap::new(),
+            formats: HashMap::new(),
         }
     }
-    pub fn finish(&self) {
+
+    /// Recreates the framebuffer and every attachment with the same formats at the new size.
+    /// The contents are lost.
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<()> {
+        if width == self.width() && height == self.height() {
+            return Ok(());
+        }
+        self.attachments.clear();
+        self.frame_buffer = FrameBuffer::new(width, height);
+        let formats: Vec<_> = self.formats.drain().collect();
+        for (attachment, format) in formats {
+            match format {
+                AttachmentFormat::Texture(format) => {
+                    self.new_texture_format(attachment, format);
+                }
+                AttachmentFormat::Buffer(format) => {
+                    self.new_buffer_format(attachment, format);
+                }
+            }
+        }
+        self.finish()
+    }
+    /// Enables drawing to every color attachment and checks that the framebuffer is complete.
+    /// Draw buffers are the color attachments in order, so location 0 of `FRAGMENT_OUTPUTS`
+    /// goes to the lowest attachment.
+    pub fn finish(&self) -> Result<()> {
         self.frame_buffer.bind();
-        self.frame_buffer.assert_status();
+        let colors = self.draw_buffers();
+        unsafe {
+            if !colors.is_empty() {
+                gl::DrawBuffers(colors.len() as i32, colors.as_ptr());
+            }
+        }
+        let status = self.frame_buffer.check_status();
         self.frame_buffer.unbind();
+        status
+    }
+    /// The color attachments in draw buffer order, see `finish`
+    fn draw_buffers(&self) -> Vec<GLenum> {
+        let mut colors: Vec<GLenum> = self.attachments.keys()
+                                          .copied()
+                                          .filter(|x| is_color(*x))
+                                          .collect();
+        colors.sort_unstable();
+        colors
     }
+
     pub fn bind(&self) {
         self.frame_buffer.bind();
     }
@@ -133,45 +204,184 @@ impl RenderTarget {
         self.frame_buffer.unbind();
     }
 
-    pub fn new_texture(&mut self, target: GLenum) -> &Texture2D {
+    pub fn width(&self) -> u32 {
+        self.frame_buffer.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.frame_buffer.height
+    }
+
+    /// `RGBA16F` for linear hdr rendering, 8 bit formats for anything that is displayed as is.
+    pub fn new_texture_format(&mut self, target: GLenum, format: TextureFormat) -> &Texture2D {
         self.frame_buffer.bind();
         let texture = Texture2D::new(self.frame_buffer.width, self.frame_buffer.height);
         texture.bind();
-        texture.put_data(0 as *const c_void, gl::RGB);
-        texture.tex_parameter(gl::TEXTURE_MIN_FILTER, gl::LINEAR);
-        texture.tex_parameter(gl::TEXTURE_MAG_FILTER, gl::LINEAR);
+        texture.put_empty(format);
+        let filter = if format.is_integer() { gl::NEAREST } else { gl::LINEAR };
+        texture.tex_parameter(gl::TEXTURE_MIN_FILTER, filter);
+        texture.tex_parameter(gl::TEXTURE_MAG_FILTER, filter);
         texture.unbind();
 
+        self.formats.insert(target, AttachmentFormat::Texture(format));
         self.attach_texture(texture, target);
         self.frame_buffer.unbind();
         self.get(target).unwrap().as_texture().unwrap()
     }
 
     pub fn new_buffer(&mut self, target: GLenum) -> &RenderBuffer {
+        self.new_buffer_format(target, gl::DEPTH24_STENCIL8)
+    }
+
+    pub fn new_buffer_format(&mut self, target: GLenum, format: GLenum) -> &RenderBuffer {
         self.frame_buffer.bind();
         let buffer = RenderBuffer::new(self.frame_buffer.width, self.frame_buffer.height);
         buffer.bind();
-        buffer.storage(gl::DEPTH24_STENCIL8);
+        buffer.storage(format);
         buffer.unbind();
 
+        self.formats.insert(target, AttachmentFormat::Buffer(format));
         self.attach_buffer(buffer, target);
         self.frame_buffer.unbind();
         self.get(target).unwrap().as_buffer().unwrap()
     }
 
-    pub fn attach_buffer(&mut self, buffer: RenderBuffer, attachment: GLenum) {
+    fn attach_buffer(&mut self, buffer: RenderBuffer, attachment: GLenum) {
         self.frame_buffer.attach_buffer(&buffer, attachment, gl::RENDERBUFFER);
         self.attachments.insert(attachment, RenderAttachment::Buffer(buffer));
     }
 
-    pub fn attach_texture(&mut self, texture: Texture2D, attachment: GLenum) {
+    fn attach_texture(&mut self, texture: Texture2D, attachment: GLenum) {
         self.frame_buffer.attach_texture(&texture, attachment);
         self.attachments.insert(attachment, RenderAttachment::Texture2D(texture));
     }
 
+    /// Detaches and deletes an attachment, call `finish` afterwards.
+    pub fn remove(&mut self, attachment: GLenum) {
+        if let Some(removed) = self.attachments.remove(&attachment) {
+            self.formats.remove(&attachment);
+            self.frame_buffer.bind();
+            unsafe {
+                match removed {
+                    RenderAttachment::Texture2D(_) => gl::FramebufferTexture2D(
+                        gl::FRAMEBUFFER, attachment, gl::TEXTURE_2D, 0, 0),
+                    RenderAttachment::Buffer(_) => gl::FramebufferRenderbuffer(
+                        gl::FRAMEBUFFER, attachment, gl::RENDERBUFFER, 0),
+                }
+            }
+            self.frame_buffer.unbind();
+        }
+    }
+
     pub fn get(&mut self, target: u32) -> Option<&RenderAttachment> {
         self.attachments.get(&target)
     }
+
+    pub fn has(&self, attachment: GLenum) -> bool {
+        self.attachments.contains_key(&attachment)
+    }
+
+    /// Clears an unsigned integer color attachment, `glClear` is undefined for those.
+    /// The target has to be bound.
+    pub fn clear_uint(&self, attachment: GLenum, value: u32) {
+        // the draw buffer index, not the attachment number, they differ when attachments are skipped
+        let Some(draw_buffer) = self.draw_buffers().iter().position(|x| *x == attachment) else {
+            return;
+        };
+        let values = [value; 4];
+        unsafe {
+            gl::ClearBufferuiv(gl::COLOR, draw_buffer as i32, values.as_ptr());
+        }
+    }
+
+    /// Reads a single texel of an unsigned integer attachment, origin at the bottom left.
+    pub fn read_uint(&self, attachment: GLenum, x: u32, y: u32) -> Option<u32> {
+        if !self.has(attachment) || x >= self.width() || y >= self.height() {
+            return None;
+        }
+        let mut value = 0u32;
+        self.read_from(attachment, || unsafe {
+            gl::ReadPixels(x as i32, y as i32, 1, 1, gl::RED_INTEGER, gl::UNSIGNED_INT,
+                           &mut value as *mut u32 as *mut c_void);
+        });
+        Some(value)
+    }
+
+    /// Reads an attachment back to the cpu, flipped so the first row is the top of the image.
+    /// Depth attachments are returned as grayscale. Integer attachments have no meaningful
+    /// color and return `None`, use `read_uint` for those.
+    pub fn read(&self, attachment: GLenum) -> Option<RgbaImage> {
+        let is_integer = match self.formats.get(&attachment)? {
+            AttachmentFormat::Texture(format) => format.is_integer(),
+            AttachmentFormat::Buffer(_) => false,
+        };
+        if is_integer {
+            return None;
+        }
+        let width = self.frame_buffer.width;
+        let height = self.frame_buffer.height;
+        let is_depth = attachment == gl::DEPTH_ATTACHMENT ||
+            attachment == gl::DEPTH_STENCIL_ATTACHMENT;
+
+        let mut image = RgbaImage::new(width, height);
+        if is_depth {
+            let mut depth = vec![0f32; (width * height) as usize];
+            self.read_from(attachment, || unsafe {
+                gl::ReadPixels(0, 0, width as i32, height as i32,
+                               gl::DEPTH_COMPONENT, gl::FLOAT,
+                               depth.as_mut_ptr() as *mut c_void);
+            });
+            for (pixel, value) in image.pixels_mut().zip(depth.iter()) {
+                let gray = (value.clamp(0.0, 1.0) * 255.0) as u8;
+                *pixel = Rgba([gray, gray, gray, 255]);
+            }
+        } else {
+            self.read_from(attachment, || unsafe {
+                gl::ReadPixels(0, 0, width as i32, height as i32,
+                               gl::RGBA, gl::UNSIGNED_BYTE,
+                               image.as_mut_ptr() as *mut c_void);
+            });
+        }
+
+        imageops::flip_vertical_in_place(&mut image);
+        Some(image)
+    }
+
+    /// Runs `read` with the attachment as read buffer and tight packing,
+    /// then puts back whatever read buffer was set before.
+    fn read_from(&self, attachment: GLenum, read: impl FnOnce()) {
+        self.frame_buffer.bind();
+        let mut previous = 0;
+        unsafe {
+            gl::GetIntegerv(gl::READ_BUFFER, &mut previous);
+            if is_color(attachment) {
+                gl::ReadBuffer(attachment);
+            }
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        }
+        read();
+        unsafe {
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 4);
+            gl::ReadBuffer(previous as GLenum);
+        }
+        self.frame_buffer.unbind();
+    }
+
+    /// Fails for missing and integer attachments, which `read` can't turn into an image.
+    pub fn save_png(&self, attachment: GLenum, path: &Path) -> Result<()> {
+        let image = self.read(attachment).ok_or_else(|| {
+            Error::io(path, io::Error::new(io::ErrorKind::InvalidInput,
+                                           format!("attachment 0x{:X} has no image to save", attachment)))
+        })?;
+        image.save_with_format(path, image::ImageFormat::Png).map_err(|e| match e {
+            ImageError::IoError(source) => Error::io(path, source),
+            source => Error::ImageDecode { path: path.to_path_buf(), source },
+        })
+    }
+}
+
+fn is_color(attachment: GLenum) -> bool {
+    (gl::COLOR_ATTACHMENT0..=gl::COLOR_ATTACHMENT31).contains(&attachment)
 }
 
 pub enum RenderAttachment {
@@ -193,4 +403,51 @@ impl RenderAttachment {
             _ => None
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::HeadlessContext;
+
+    #[test]
+    fn clear_uint_with_skipped_attachments() {
+        let _context = HeadlessContext::new()
+            .unwrap_or_else(|e| panic!("needs a surfaceless EGL context: {}", e));
+        let mut target = RenderTarget::new(4, 4);
+        target.new_texture_format(gl::COLOR_ATTACHMENT0, TextureFormat::RGBA8);
+        target.new_texture_format(gl::COLOR_ATTACHMENT2, TextureFormat::R32UI);
+        target.new_texture_format(gl::COLOR_ATTACHMENT3, TextureFormat::R32UI);
+        target.finish().unwrap();
+
+        target.bind();
+        target.clear_uint(gl::COLOR_ATTACHMENT2, 7);
+        target.clear_uint(gl::COLOR_ATTACHMENT3, 9);
+        target.unbind();
+        assert_eq!(target.read_uint(gl::COLOR_ATTACHMENT2, 1, 2), Some(7));
+        assert_eq!(target.read_uint(gl::COLOR_ATTACHMENT3, 3, 0), Some(9));
+        assert_eq!(unsafe { gl::GetError() }, gl::NO_ERROR);
+    }
+
+    #[test]
+    fn save_png_errors() {
+        let _context = HeadlessContext::new()
+            .unwrap_or_else(|e| panic!("needs a surfaceless EGL context: {}", e));
+        let mut target = RenderTarget::new(4, 4);
+        target.new_texture_format(gl::COLOR_ATTACHMENT0, TextureFormat::RGBA8);
+        target.new_texture_format(gl::COLOR_ATTACHMENT1, TextureFormat::R32UI);
+        target.finish().unwrap();
+
+        let path = std::env::temp_dir().join("save_png_errors.png");
+        for attachment in [gl::COLOR_ATTACHMENT1, gl::COLOR_ATTACHMENT2] {
+            match target.save_png(attachment, &path) {
+                Err(Error::Io { source, .. }) => assert_eq!(source.kind(), io::ErrorKind::InvalidInput),
+                other => panic!("0x{:X}: {:?}", attachment, other),
+            }
+        }
+        let missing = Path::new("does/not/exist.png");
+        assert!(matches!(target.save_png(gl::COLOR_ATTACHMENT0, missing), Err(Error::Io { .. })));
+        target.save_png(gl::COLOR_ATTACHMENT0, &path).unwrap();
+        assert_eq!(image::open(&path).unwrap().width(), 4);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,4 +1,6 @@
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Vec2, Vec3};
+
+use crate::geometry::{BoundingSphere, Ray};
 
 pub struct Camera {
     pub yaw: f64,
@@ -13,7 +15,7 @@ pub struct Camera {
 
 impl Camera {
     pub fn new(fov: f32, speed: f32) -> Camera {
-        let camera = Camera {
+        Camera {
             yaw: 0.0,
             pitch: 0.0,
             position: Vec3::splat(0.0),
@@ -22,8 +24,7 @@ impl Camera {
             speed,
             near_plane: 1.0,
             far_plane: 100000.0
-        };
-        camera
+        }
     }
     pub fn front(&self) -> Vec3 {
         Vec3 {
@@ -33,7 +34,32 @@ impl Camera {
         }.normalize()
     }
 
+    pub fn projection(&self, aspect: f32) -> Mat4 {
+        Mat4::perspective_rh_gl(self.fov.to_radians(), aspect, self.near_plane, self.far_plane)
+    }
+
     pub fn matrix(&self) -> Mat4 {
         Mat4::look_at_lh(self.position, self.position + self.front(), self.up)
     }
+
+    /// Moves the camera back along its view direction until the sphere fits the field of view,
+    /// and scales speed and clip planes with it, so sponza and the teapot both get a usable camera.
+    pub fn frame(&mut self, sphere: &BoundingSphere) {
+        let radius = sphere.radius.max(f32::EPSILON);
+        let distance = radius / (self.fov.to_radians() * 0.5).sin();
+        // the view looks along -front, see the movement in `Scene::handle_keys`
+        self.position = sphere.center + self.front() * distance;
+        self.speed = radius * 0.1;
+        self.near_plane = (distance - radius).max(radius * 0.001);
+        self.far_plane = distance + radius * 10.0;
+    }
+
+    /// The world space ray through a point given in normalized device coordinates,
+    /// starting on the near plane.
+    pub fn ray(&self, aspect: f32, ndc: Vec2) -> Ray {
+        let inverse = (self.projection(aspect) * self.matrix()).inverse();
+        let near = inverse.project_point3(ndc.extend(-1.0));
+        let far = inverse.project_point3(ndc.extend(1.0));
+        Ray::new(near, far - near)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,96 @@
+use std::{fmt, io};
+use std::path::{Path, PathBuf};
+
+use gl::types::GLenum;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Io { path: PathBuf, source: io::Error },
+    ShaderConfig { path: PathBuf, message: String },
+    ShaderCompile { stage: GLenum, log: String },
+    ShaderLink { log: String },
+    ImageDecode { path: PathBuf, source: image::ImageError },
+    ObjParse { path: PathBuf, source: tobj::LoadError },
+    IncompleteFramebuffer { status: GLenum },
+    LayoutMismatch(String),
+    /// images that can't go into the same texture, like a non square cubemap face
+    ImageSize(String),
+    Context(String),
+}
+
+impl Error {
+    pub fn io(path: &Path, source: io::Error) -> Error {
+        Error::Io { path: path.to_path_buf(), source }
+    }
+
+    pub fn not_found(path: &Path) -> Error {
+        Error::io(path, io::Error::new(io::ErrorKind::NotFound, "file does not exist"))
+    }
+}
+
+pub fn stage_name(stage: GLenum) -> &'static str {
+    match stage {
+        gl::VERTEX_SHADER => "vertex",
+        gl::FRAGMENT_SHADER => "fragment",
+        gl::GEOMETRY_SHADER => "geometry",
+        gl::COMPUTE_SHADER => "compute",
+        gl::TESS_CONTROL_SHADER => "tessellation control",
+        gl::TESS_EVALUATION_SHADER => "tessellation evaluation",
+        _ => "unknown",
+    }
+}
+
+fn framebuffer_status_name(status: GLenum) -> &'static str {
+    match status {
+        gl::FRAMEBUFFER_UNDEFINED => "FRAMEBUFFER_UNDEFINED",
+        gl::FRAMEBUFFER_INCOMPLETE_ATTACHMENT => "FRAMEBUFFER_INCOMPLETE_ATTACHMENT",
+        gl::FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT => "FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT",
+        gl::FRAMEBUFFER_INCOMPLETE_DRAW_BUFFER => "FRAMEBUFFER_INCOMPLETE_DRAW_BUFFER",
+        gl::FRAMEBUFFER_INCOMPLETE_READ_BUFFER => "FRAMEBUFFER_INCOMPLETE_READ_BUFFER",
+        gl::FRAMEBUFFER_UNSUPPORTED => "FRAMEBUFFER_UNSUPPORTED",
+        gl::FRAMEBUFFER_INCOMPLETE_MULTISAMPLE => "FRAMEBUFFER_INCOMPLETE_MULTISAMPLE",
+        gl::FRAMEBUFFER_INCOMPLETE_LAYER_TARGETS => "FRAMEBUFFER_INCOMPLETE_LAYER_TARGETS",
+        _ => "unknown status",
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::ShaderConfig { path, message } => {
+                write!(f, "invalid shader config {}: {}", path.display(), message)
+            }
+            Error::ShaderCompile { stage, log } => {
+                write!(f, "{} shader failed to compile:\n{}", stage_name(*stage), log)
+            }
+            Error::ShaderLink { log } => write!(f, "shader program failed to link:\n{}", log),
+            Error::ImageDecode { path, source } => {
+                write!(f, "could not load image {}: {}", path.display(), source)
+            }
+            Error::ObjParse { path, source } => {
+                write!(f, "could not load obj {}: {}", path.display(), source)
+            }
+            Error::IncompleteFramebuffer { status } => {
+                write!(f, "framebuffer is not complete: {} (0x{:X})",
+                       framebuffer_status_name(*status), status)
+            }
+            Error::LayoutMismatch(message) => write!(f, "vertex layout mismatch: {}", message),
+            Error::ImageSize(message) => write!(f, "images don't fit the texture: {}", message),
+            Error::Context(message) => write!(f, "could not create gl context: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::ImageDecode { source, .. } => Some(source),
+            Error::ObjParse { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
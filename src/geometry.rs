@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+
+use glam::{Mat4, Vec2, Vec3, Vec4};
+
+/// `direction` is normalized, so `t` along the ray is a distance.
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, direction: Vec3) -> Ray {
+        Ray {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + self.direction * t
+    }
+}
+
+/// Axis aligned bounding box, `empty()` has min above max so growing it works from nothing.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vec3::splat(f32::INFINITY),
+            max: Vec3::splat(f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Aabb {
+        points.into_iter().fold(Aabb::empty(), |bounds, x| bounds.grow(x))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.cmpgt(self.max).any()
+    }
+
+    pub fn grow(self, point: Vec3) -> Aabb {
+        Aabb {
+            min: self.min.min(point),
+            max: self.max.max(point),
+        }
+    }
+
+    pub fn union(self, other: Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn size(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    /// The box around the transformed box, so it grows under rotation.
+    pub fn transform(&self, matrix: &Mat4) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+        // Arvo: each output axis is the sum of the smaller and larger products per input axis
+        let mut min = matrix.w_axis.truncate();
+        let mut max = min;
+        for (axis, (low, high)) in [(self.min.x, self.max.x), (self.min.y, self.max.y),
+                                    (self.min.z, self.max.z)].into_iter().enumerate() {
+            let column = matrix.col(axis).truncate();
+            let a = column * low;
+            let b = column * high;
+            min += a.min(b);
+            max += a.max(b);
+        }
+        Aabb { min, max }
+    }
+
+    /// Distance along the ray where it enters the box, 0 if it starts inside.
+    /// Slab test, `inverse_direction` is `1 / ray.direction` so it's only computed once per ray.
+    pub fn intersect(&self, ray: &Ray, inverse_direction: Vec3, max_t: f32) -> Option<f32> {
+        let t0 = (self.min - ray.origin) * inverse_direction;
+        let t1 = (self.max - ray.origin) * inverse_direction;
+        let near = t0.min(t1).max_element().max(0.0);
+        let far = t0.max(t1).min_element().min(max_t);
+        if near <= far {
+            Some(near)
+        } else {
+            None
+        }
+    }
+}
+
+/// Not the minimal sphere, it is centered on the bounding box of the points.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    pub fn from_points(points: &[Vec3]) -> BoundingSphere {
+        let center = Aabb::from_points(points.iter().copied()).center();
+        let radius = points.iter().map(|x| x.distance(center)).fold(0.0, f32::max);
+        BoundingSphere {
+            center: if points.is_empty() { Vec3::ZERO } else { center },
+            radius,
+        }
+    }
+
+    /// The sphere containing both
+    pub fn union(self, other: BoundingSphere) -> BoundingSphere {
+        let offset = other.center - self.center;
+        let distance = offset.length();
+        if distance + other.radius <= self.radius {
+            return self;
+        }
+        if distance + self.radius <= other.radius {
+            return other;
+        }
+        let radius = (distance + self.radius + other.radius) * 0.5;
+        BoundingSphere {
+            center: self.center + offset / distance * (radius - self.radius),
+            radius,
+        }
+    }
+
+    /// Scales the radius by the largest axis scale, so it stays conservative for non uniform scale.
+    pub fn transform(&self, matrix: &Mat4) -> BoundingSphere {
+        let scale = matrix.x_axis.truncate().length()
+                          .max(matrix.y_axis.truncate().length())
+                          .max(matrix.z_axis.truncate().length());
+        BoundingSphere {
+            center: matrix.transform_point3(self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+/// Builds normals for an indexed mesh that has none. The normals of faces sharing a position
+/// are averaged if they are at most `angle` degrees apart, 0 gives flat shading.
+/// Returns the new vertex list as (source vertex, normal) pairs and the new indices,
+/// vertices are only split where the normals differ.
+pub fn generate_normals(positions: &[Vec3], indices: &[u32], angle: f32) -> (Vec<(u32, Vec3)>, Vec<u32>) {
+    let face_count = indices.len() / 3;
+    let mut face_normals = Vec::with_capacity(face_count);
+    for face in indices.chunks_exact(3) {
+        let a = positions[face[0] as usize];
+        let b = positions[face[1] as usize];
+        let c = positions[face[2] as usize];
+        // not normalized, so larger faces have more weight
+        face_normals.push((b - a).cross(c - a));
+    }
+
+    let mut faces_at: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+    for (face, corners) in indices.chunks_exact(3).enumerate() {
+        for index in corners.iter() {
+            faces_at.entry(position_key(positions[*index as usize]))
+                    .or_default()
+                    .push(face);
+        }
+    }
+
+    let mut vertices: Vec<(u32, Vec3)> = Vec::new();
+    let mut new_indices = Vec::with_capacity(indices.len());
+    let mut lookup: HashMap<(u32, [u32; 3]), u32> = HashMap::new();
+    let threshold = angle.to_radians().cos();
+
+    for (face, corners) in indices.chunks_exact(3).enumerate() {
+        let face_normal = face_normals[face].normalize_or_zero();
+        for index in corners.iter() {
+            let mut sum = Vec3::ZERO;
+            for other in faces_at[&position_key(positions[*index as usize])].iter() {
+                let other_normal = face_normals[*other];
+                if face_normal.dot(other_normal.normalize_or_zero()) >= threshold {
+                    sum += other_normal;
+                }
+            }
+            let sum = sum.normalize_or_zero();
+            let normal = if sum == Vec3::ZERO { face_normal } else { sum };
+            let key = (*index, position_key(normal));
+            let next = vertices.len() as u32;
+            let vertex = *lookup.entry(key).or_insert_with(|| {
+                vertices.push((*index, normal));
+                next
+            });
+            new_indices.push(vertex);
+        }
+    }
+    (vertices, new_indices)
+}
+
+/// Per vertex tangents in the spirit of MikkTSpace, the tangent space most bakers use.
+/// The uv derived tangent of every face is projected into the tangent plane of each corner's
+/// normal and weighted by the corner angle, and faces are only averaged with neighbours of the
+/// same uv orientation. Vertices on mirrored uv seams are split, so like `generate_normals`
+/// this returns the new vertex list as (source vertex, tangent) pairs and the new indices.
+/// w is the handedness, so `bitangent = cross(normal, tangent.xyz) * w`.
+/// Faces without usable uvs take the tangent of their vertices from the other faces.
+pub fn generate_tangents(positions: &[Vec3], normals: &[Vec3], uvs: &[Vec2], indices: &[u32])
+                         -> (Vec<(u32, Vec4)>, Vec<u32>) {
+    // uv orientation of every face, 0 for degenerate uvs
+    let mut orientations = Vec::with_capacity(indices.len() / 3);
+    let mut sums: HashMap<(u32, i8), Vec3> = HashMap::new();
+
+    for face in indices.chunks_exact(3) {
+        let corners = [face[0] as usize, face[1] as usize, face[2] as usize];
+        let edge1 = positions[corners[1]] - positions[corners[0]];
+        let edge2 = positions[corners[2]] - positions[corners[0]];
+        let delta1 = uvs[corners[1]] - uvs[corners[0]];
+        let delta2 = uvs[corners[2]] - uvs[corners[0]];
+
+        let determinant = delta1.x * delta2.y - delta2.x * delta1.y;
+        if determinant.abs() < f32::EPSILON {
+            orientations.push(0);
+            continue;
+        }
+        let orientation = if determinant > 0.0 { 1 } else { -1 };
+        orientations.push(orientation);
+        // only the direction matters, every corner normalizes its projection
+        let tangent = (edge1 * delta2.y - edge2 * delta1.y) * determinant.signum();
+
+        for (corner, index) in corners.iter().enumerate() {
+            let normal = normals[*index];
+            let position = positions[*index];
+            let to_next = project_to_plane(positions[corners[(corner + 1) % 3]] - position, normal);
+            let to_previous = project_to_plane(positions[corners[(corner + 2) % 3]] - position, normal);
+            let angle = to_next.dot(to_previous).clamp(-1.0, 1.0).acos();
+            *sums.entry((face[corner], orientation)).or_default() += project_to_plane(tangent, normal) * angle;
+        }
+    }
+
+    let mut vertices: Vec<(u32, Vec4)> = Vec::new();
+    let mut new_indices = Vec::with_capacity(indices.len());
+    let mut lookup: HashMap<(u32, i8), u32> = HashMap::new();
+    for (face, corners) in indices.chunks_exact(3).enumerate() {
+        for index in corners.iter() {
+            let orientation = match orientations[face] {
+                0 if !sums.contains_key(&(*index, 1)) && sums.contains_key(&(*index, -1)) => -1,
+                0 => 1,
+                orientation => orientation,
+            };
+            let next = vertices.len() as u32;
+            let vertex = *lookup.entry((*index, orientation)).or_insert_with(|| {
+                let normal = normals[*index as usize];
+                let sum = sums.get(&(*index, orientation)).copied().unwrap_or(Vec3::ZERO);
+                let mut tangent = project_to_plane(sum, normal);
+                if tangent == Vec3::ZERO {
+                    tangent = any_perpendicular(normal);
+                }
+                vertices.push((*index, tangent.extend(orientation as f32)));
+                next
+            });
+            new_indices.push(vertex);
+        }
+    }
+    (vertices, new_indices)
+}
+
+/// Normalized, zero if `vec` is parallel to `normal`
+fn project_to_plane(vec: Vec3, normal: Vec3) -> Vec3 {
+    (vec - normal * normal.dot(vec)).normalize_or_zero()
+}
+
+fn any_perpendicular(normal: Vec3) -> Vec3 {
+    let axis = if normal.x.abs() < 0.9 { Vec3::X } else { Vec3::Y };
+    normal.cross(axis).normalize_or_zero()
+}
+
+fn position_key(vec: Vec3) -> [u32; 3] {
+    [vec.x.to_bits(), vec.y.to_bits(), vec.z.to_bits()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corners(bounds: &Aabb) -> Vec<Vec3> {
+        (0..8).map(|i| Vec3::new(if i & 1 == 0 { bounds.min.x } else { bounds.max.x },
+                                 if i & 2 == 0 { bounds.min.y } else { bounds.max.y },
+                                 if i & 4 == 0 { bounds.min.z } else { bounds.max.z }))
+              .collect()
+    }
+
+    #[test]
+    fn aabb_transform() {
+        let bounds = Aabb { min: Vec3::new(-1.0, -2.0, 0.0), max: Vec3::new(1.0, 2.0, 3.0) };
+        let translated = bounds.transform(&Mat4::from_translation(Vec3::new(5.0, 0.0, -1.0)));
+        assert_eq!(translated, Aabb { min: Vec3::new(4.0, -2.0, -1.0), max: Vec3::new(6.0, 2.0, 2.0) });
+
+        let quarter = bounds.transform(&Mat4::from_rotation_z(90f32.to_radians()));
+        assert!(quarter.min.abs_diff_eq(Vec3::new(-2.0, -1.0, 0.0), 1e-5), "{:?}", quarter);
+        assert!(quarter.max.abs_diff_eq(Vec3::new(2.0, 1.0, 3.0), 1e-5), "{:?}", quarter);
+
+        // same as the box around the transformed corners
+        let matrix = Mat4::from_translation(Vec3::new(1.0, -3.0, 2.0)) *
+            Mat4::from_rotation_y(0.7) * Mat4::from_rotation_x(-1.2) * Mat4::from_scale(Vec3::new(2.0, 1.0, 0.5));
+        let transformed = bounds.transform(&matrix);
+        let expected = Aabb::from_points(corners(&bounds).into_iter().map(|x| matrix.transform_point3(x)));
+        assert!(transformed.min.abs_diff_eq(expected.min, 1e-5) && transformed.max.abs_diff_eq(expected.max, 1e-5),
+                "{:?} {:?}", transformed, expected);
+
+        assert!(Aabb::empty().transform(&matrix).is_empty());
+    }
+
+    fn contains(outer: &BoundingSphere, inner: &BoundingSphere) -> bool {
+        outer.center.distance(inner.center) + inner.radius <= outer.radius + 1e-5
+    }
+
+    #[test]
+    fn sphere_union_contains_both() {
+        let a = BoundingSphere { center: Vec3::ZERO, radius: 1.0 };
+        let cases = [
+            BoundingSphere { center: Vec3::new(4.0, 0.0, 0.0), radius: 1.0 },
+            BoundingSphere { center: Vec3::new(0.5, 0.0, 0.0), radius: 0.25 },
+            BoundingSphere { center: Vec3::new(0.0, 1.0, -1.0), radius: 3.0 },
+            BoundingSphere { center: Vec3::new(1.0, 1.0, 1.0), radius: 1.0 },
+            a,
+        ];
+        for b in cases {
+            for union in [a.union(b), b.union(a)] {
+                assert!(contains(&union, &a) && contains(&union, &b), "{:?} {:?} {:?}", a, b, union);
+                // tight, never larger than the sphere around both ends
+                let largest = a.radius.max(b.radius);
+                let bound = (a.center.distance(b.center) + a.radius + b.radius) * 0.5;
+                assert!(union.radius <= largest.max(bound) + 1e-5, "{:?}", union);
+            }
+        }
+        let disjoint = a.union(cases[0]);
+        assert!(disjoint.center.abs_diff_eq(Vec3::new(2.0, 0.0, 0.0), 1e-5));
+        assert_eq!(disjoint.radius, 3.0);
+        assert_eq!(a.union(cases[1]), a);
+        assert_eq!(a.union(cases[2]), cases[2]);
+    }
+
+    #[test]
+    fn sphere_from_degenerate_points() {
+        assert_eq!(BoundingSphere::from_points(&[]), BoundingSphere { center: Vec3::ZERO, radius: 0.0 });
+
+        let point = Vec3::new(1.0, -2.0, 3.0);
+        assert_eq!(BoundingSphere::from_points(&[point, point, point]),
+                   BoundingSphere { center: point, radius: 0.0 });
+
+        // collinear points, the box is flat in two axes
+        let line = [Vec3::new(0.0, 1.0, 1.0), Vec3::new(4.0, 1.0, 1.0), Vec3::new(1.0, 1.0, 1.0)];
+        let sphere = BoundingSphere::from_points(&line);
+        assert_eq!(sphere, BoundingSphere { center: Vec3::new(2.0, 1.0, 1.0), radius: 2.0 });
+        assert!(line.iter().all(|x| x.distance(sphere.center) <= sphere.radius));
+    }
+
+    /// Two triangles sharing the edge from vertex 0 to 1, the second one bent up by `degrees`
+    fn fold(degrees: f32) -> Vec<Vec3> {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        vec![Vec3::ZERO, Vec3::X, Vec3::Y, Vec3::new(0.0, -cos, sin)]
+    }
+
+    const FOLD_INDICES: [u32; 6] = [0, 1, 2, 0, 3, 1];
+
+    fn normals_of(vertices: &[(u32, Vec3)], source: u32) -> Vec<Vec3> {
+        vertices.iter().filter(|x| x.0 == source).map(|x| x.1).collect()
+    }
+
+    #[test]
+    fn smoothed_normals_below_threshold() {
+        let (vertices, indices) = generate_normals(&fold(30.0), &FOLD_INDICES, 45.0);
+        assert_eq!(vertices.len(), 4);
+        let (sin, cos) = 15f32.to_radians().sin_cos();
+        for shared in [0, 1] {
+            let normals = normals_of(&vertices, shared);
+            assert_eq!(normals.len(), 1);
+            assert!(normals[0].abs_diff_eq(Vec3::new(0.0, sin, cos), 1e-5), "{}", normals[0]);
+        }
+        assert!(normals_of(&vertices, 2)[0].abs_diff_eq(Vec3::Z, 1e-5));
+        let sources: Vec<u32> = indices.iter().map(|x| vertices[*x as usize].0).collect();
+        assert_eq!(sources, FOLD_INDICES);
+    }
+
+    #[test]
+    fn flat_normals_above_threshold() {
+        let (sin, cos) = 60f32.to_radians().sin_cos();
+        for (degrees, angle) in [(60.0, 45.0), (30.0, 0.0)] {
+            let (vertices, indices) = generate_normals(&fold(degrees), &FOLD_INDICES, angle);
+            assert_eq!(vertices.len(), 6);
+            assert_eq!(indices, [0, 1, 2, 3, 4, 5]);
+            if degrees == 60.0 {
+                let normals = normals_of(&vertices, 0);
+                assert!(normals[0].abs_diff_eq(Vec3::Z, 1e-5));
+                assert!(normals[1].abs_diff_eq(Vec3::new(0.0, sin, cos), 1e-5));
+            }
+        }
+    }
+
+    #[test]
+    fn tangent_handedness() {
+        let positions = [Vec3::ZERO, Vec3::X, Vec3::Y];
+        let normals = [Vec3::Z; 3];
+        let uvs = [Vec2::ZERO, Vec2::X, Vec2::Y];
+        let (tangents, _) = generate_tangents(&positions, &normals, &uvs, &[0, 1, 2]);
+        for (_, tangent) in tangents.iter() {
+            assert!(tangent.abs_diff_eq(Vec4::new(1.0, 0.0, 0.0, 1.0), 1e-5), "{}", tangent);
+        }
+
+        // u runs the other way, the bitangent still has to follow v
+        let mirrored = [Vec2::X, Vec2::ZERO, Vec2::new(1.0, 1.0)];
+        let (tangents, _) = generate_tangents(&positions, &normals, &mirrored, &[0, 1, 2]);
+        for (_, tangent) in tangents.iter() {
+            assert!(tangent.abs_diff_eq(Vec4::new(-1.0, 0.0, 0.0, -1.0), 1e-5), "{}", tangent);
+            let bitangent = Vec3::Z.cross(tangent.truncate()) * tangent.w;
+            assert!(bitangent.abs_diff_eq(Vec3::Y, 1e-5));
+        }
+    }
+
+    #[test]
+    fn tangents_split_on_mirrored_seams() {
+        // the right half is mapped as is, the left half mirrored around x = 0
+        let positions = [Vec3::ZERO, Vec3::X, Vec3::Y, Vec3::NEG_X];
+        let normals = [Vec3::Z; 4];
+        let uvs = [Vec2::ZERO, Vec2::X, Vec2::Y, Vec2::X];
+        let (tangents, indices) = generate_tangents(&positions, &normals, &uvs, &[0, 1, 2, 0, 2, 3]);
+        assert_eq!(tangents.len(), 6);
+        for (corner, index) in indices.iter().enumerate() {
+            let tangent = tangents[*index as usize].1;
+            let expected = if corner < 3 { Vec4::new(1.0, 0.0, 0.0, 1.0) } else { Vec4::new(-1.0, 0.0, 0.0, -1.0) };
+            assert!(tangent.abs_diff_eq(expected, 1e-5), "corner {}: {}", corner, tangent);
+        }
+    }
+
+    #[test]
+    fn tangents_follow_the_normal() {
+        // a smoothed normal tilted away from the face still gets a perpendicular tangent
+        let positions = [Vec3::ZERO, Vec3::X, Vec3::Y];
+        let tilted = Vec3::new(0.5, 0.0, 1.0).normalize();
+        let normals = [tilted, Vec3::Z, Vec3::Z];
+        let uvs = [Vec2::ZERO, Vec2::X, Vec2::Y];
+        let degenerate = [Vec2::ZERO; 3];
+        let (tangents, _) = generate_tangents(&positions, &normals, &uvs, &[0, 1, 2]);
+        let (fallback, _) = generate_tangents(&positions, &normals, &degenerate, &[0, 1, 2]);
+        for (source, tangent) in tangents.iter().chain(fallback.iter()) {
+            let normal = normals[*source as usize];
+            assert!(normal.dot(tangent.truncate()).abs() < 1e-5);
+            assert!((tangent.truncate().length() - 1.0).abs() < 1e-5);
+        }
+    }
+}
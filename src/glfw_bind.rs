@@ -64,7 +64,7 @@
 //! }
 //! ```
 
-/// Use the reexported glfw crate to avoid version conflicts. extern crate glfw;
+// Use the reexported glfw crate to avoid version conflicts. extern crate glfw;
 
 use std::ffi::CStr;
 use std::os::raw::{c_char, c_void};
@@ -110,7 +110,7 @@ impl ImguiGLFW {
             imgui.set_clipboard_backend(GlfwClipboardBackend(window_ptr));
         }
 
-        let mut io_mut = imgui.io_mut();
+        let io_mut = imgui.io_mut();
         io_mut.key_map[ImGuiKey::Tab as usize] = Key::Tab as u32;
         io_mut.key_map[ImGuiKey::LeftArrow as usize] = Key::Left as u32;
         io_mut.key_map[ImGuiKey::RightArrow as usize] = Key::Right as u32;
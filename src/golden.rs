@@ -0,0 +1,297 @@
+//! Golden-image regression tests.
+//!
+//! Renders fixed scenes from fixed camera poses into an offscreen `RenderTarget`, reads the
+//! presented (srgb encoded) image back and compares it against the reference PNGs in `res/golden`.
+//! Runs with `cargo test` on a surfaceless context, `GOLDEN_BLESS=1 cargo test golden`
+//! (re)writes the references. Failures leave the actual and diff images in `target/golden`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use glam::{Mat4, Vec3};
+use image::{Rgba, RgbaImage};
+
+use crate::buffer::RenderTarget;
+use crate::camera::Camera;
+use crate::assets::AssetManager;
+use crate::error::Result;
+use crate::headless::HeadlessContext;
+use crate::model::{TexVertex, Vertex};
+use crate::present::{Presenter, Tonemap};
+use crate::rendering::shader_mode;
+use crate::shader::Shader;
+use crate::textures::TextureFormat;
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+const REFERENCE_DIR: &str = "res/golden";
+const OUTPUT_DIR: &str = "target/golden";
+const BLESS_VARIABLE: &str = "GOLDEN_BLESS";
+
+pub enum CaseModel {
+    Flat(&'static str),
+    Textured(&'static str),
+}
+
+pub enum CaseShader {
+    Directory(&'static str),
+    Toml(&'static str),
+}
+
+pub struct GoldenCase {
+    pub name: &'static str,
+    pub model: CaseModel,
+    pub shader: CaseShader,
+    pub position: Vec3,
+    pub yaw: f64,
+    pub pitch: f64,
+    pub fov: f32,
+    /// maximum difference per color channel before a pixel counts as mismatched
+    pub tolerance: u8,
+}
+
+impl GoldenCase {
+    fn camera(&self) -> Camera {
+        let mut camera = Camera::new(self.fov, 0.0);
+        camera.position = self.position;
+        camera.yaw = self.yaw;
+        camera.pitch = self.pitch;
+        camera
+    }
+}
+
+pub struct Comparison {
+    pub mismatched: usize,
+    pub max_difference: u8,
+    pub diff: RgbaImage,
+}
+
+impl Comparison {
+    pub fn passed(&self) -> bool {
+        self.mismatched == 0
+    }
+}
+
+/// Compares two images channel by channel. Mismatched pixels are red in the diff image,
+/// matching pixels are a dimmed copy of the expected image.
+pub fn compare(actual: &RgbaImage, expected: &RgbaImage, tolerance: u8) -> Comparison {
+    let mut diff = RgbaImage::new(expected.width(), expected.height());
+    if actual.dimensions() != expected.dimensions() {
+        for pixel in diff.pixels_mut() {
+            *pixel = Rgba([255, 0, 0, 255]);
+        }
+        return Comparison {
+            mismatched: (expected.width() * expected.height()) as usize,
+            max_difference: 255,
+            diff,
+        };
+    }
+
+    let mut mismatched = 0;
+    let mut max_difference = 0;
+    for ((a, e), d) in actual.pixels().zip(expected.pixels()).zip(diff.pixels_mut()) {
+        let difference = a.0.iter().zip(e.0.iter())
+                            .map(|(x, y)| (*x as i16 - *y as i16).unsigned_abs() as u8)
+                            .max()
+                            .unwrap_or(0);
+        max_difference = max_difference.max(difference);
+        if difference > tolerance {
+            mismatched += 1;
+            *d = Rgba([255, 0, 0, 255]);
+        } else {
+            *d = Rgba([e[0] / 4, e[1] / 4, e[2] / 4, 255]);
+        }
+    }
+    Comparison {
+        mismatched,
+        max_difference,
+        diff,
+    }
+}
+
+fn load_shader(shader: &CaseShader) -> Result<Shader> {
+    match shader {
+        CaseShader::Directory(path) => Shader::load(Path::new(path)),
+        CaseShader::Toml(path) => Shader::from_toml(Path::new(path)),
+    }
+}
+
+fn render_case(case: &GoldenCase) -> Result<Option<RgbaImage>> {
+    let model_path = Path::new(match case.model {
+        CaseModel::Flat(path) => path,
+        CaseModel::Textured(path) => path,
+    });
+
+    let mut target = RenderTarget::new(WIDTH, HEIGHT);
+    target.new_texture_format(gl::COLOR_ATTACHMENT0, TextureFormat::RGBA16F);
+    target.new_buffer(gl::DEPTH_STENCIL_ATTACHMENT);
+    target.finish()?;
+    // clamp instead of a curve, so the references only change with the scene
+    let mut presenter = Presenter::new(WIDTH, HEIGHT)?;
+    presenter.tonemap = Tonemap::Clamp;
+
+    let shader = load_shader(&case.shader)?;
+    let camera = case.camera();
+    let projection = camera.projection(WIDTH as f32 / HEIGHT as f32);
+    let view = camera.matrix();
+    let model = Mat4::from_translation(Vec3::new(0.0, 0.0, -1.0));
+
+    target.bind();
+    unsafe {
+        gl::Viewport(0, 0, WIDTH as i32, HEIGHT as i32);
+        gl::ClearColor(0.1, 0.3, 0.51, 1.0);
+        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        gl::Enable(gl::DEPTH_TEST);
+        gl::Enable(gl::CULL_FACE);
+        gl::CullFace(gl::BACK);
+    }
+    shader_mode::fill();
+    shader.bind();
+    shader.load_mat4("projection", &projection);
+    shader.load_mat4("view", &view);
+    shader.load_mat4("model", &model);
+
+    let mut assets = AssetManager::new();
+    match case.model {
+        CaseModel::Flat(_) => {
+            let model = assets.model::<Vertex>(model_path)?;
+            model.render_with_ids(&shader, |_| 0);
+        }
+        CaseModel::Textured(_) => {
+            let model = assets.model::<TexVertex>(model_path)?;
+            model.render_with_ids(&shader, |_| 0);
+        }
+    }
+
+    shader.unbind();
+    target.unbind();
+    if let Some(color) = target.get(gl::COLOR_ATTACHMENT0).and_then(|x| x.as_texture()) {
+        presenter.present(color);
+    }
+    unsafe {
+        gl::Finish();
+    }
+
+    Ok(presenter.target.read(gl::COLOR_ATTACHMENT0))
+}
+
+/// Renders a case on its own headless context and compares it against its reference,
+/// panics with what went wrong otherwise.
+pub fn check(case: &GoldenCase) {
+    let _context = HeadlessContext::new()
+        .unwrap_or_else(|e| panic!("golden tests need a surfaceless EGL context: {}", e));
+    let actual = match render_case(case) {
+        Ok(Some(actual)) => actual,
+        Ok(None) => panic!("{}: the presented image could not be read back", case.name),
+        Err(error) => panic!("{}: {}", case.name, error),
+    };
+
+    let output = Path::new(OUTPUT_DIR);
+    fs::create_dir_all(output).expect("Failed to create golden output directory");
+    let reference: PathBuf = Path::new(REFERENCE_DIR).join(format!("{}.png", case.name));
+    let actual_path = output.join(format!("{}.actual.png", case.name));
+
+    if std::env::var_os(BLESS_VARIABLE).is_some() {
+        fs::create_dir_all(REFERENCE_DIR).expect("Failed to create golden reference directory");
+        actual.save(&reference).expect("Failed to write reference image");
+        println!("[golden] {} blessed", case.name);
+        return;
+    }
+
+    let expected = match image::open(&reference) {
+        Ok(expected) => expected.to_rgba8(),
+        Err(error) => {
+            actual.save(&actual_path).expect("Failed to write golden output");
+            panic!("{}: no reference at {:?} ({}), bless it with {}=1",
+                   case.name, reference, error, BLESS_VARIABLE);
+        }
+    };
+
+    let comparison = compare(&actual, &expected, case.tolerance);
+    if !comparison.passed() {
+        let diff_path = output.join(format!("{}.diff.png", case.name));
+        actual.save(&actual_path).expect("Failed to write golden output");
+        comparison.diff.save(&diff_path).expect("Failed to write golden diff");
+        panic!("{}: {} pixels differ by more than {} (max {}), see {:?}",
+               case.name, comparison.mismatched, case.tolerance, comparison.max_difference, diff_path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn teapot_flat() {
+        check(&GoldenCase {
+            name: "teapot_flat",
+            model: CaseModel::Flat("res/model/teapot.obj"),
+            shader: CaseShader::Directory("res/shader/flat"),
+            // from the side, the view looks along -front and yaw 90 is +z
+            position: Vec3::new(5.0, 60.0, 250.0),
+            yaw: 90.0,
+            pitch: 10.0,
+            fov: 55.0,
+            tolerance: 2,
+        });
+    }
+
+    #[test]
+    #[ignore = "sponza.obj is not checked in, put it into res/model/sponza and run with --ignored"]
+    fn sponza_textured() {
+        check(&GoldenCase {
+            name: "sponza_textured",
+            model: CaseModel::Textured("res/model/sponza/sponza.obj"),
+            shader: CaseShader::Toml("res/shader/textured.toml"),
+            position: Vec3::new(0.0, 150.0, 0.0),
+            yaw: 0.0,
+            pitch: 0.0,
+            fov: 55.0,
+            tolerance: 4,
+        });
+    }
+
+    fn gradient() -> RgbaImage {
+        RgbaImage::from_fn(8, 4, |x, y| Rgba([x as u8 * 30, y as u8 * 60, 100, 255]))
+    }
+
+    #[test]
+    fn identical_images_pass() {
+        let comparison = compare(&gradient(), &gradient(), 0);
+        assert!(comparison.passed());
+        assert_eq!(comparison.max_difference, 0);
+        assert_eq!(*comparison.diff.get_pixel(3, 2), Rgba([90 / 4, 120 / 4, 100 / 4, 255]));
+    }
+
+    #[test]
+    fn differences_within_tolerance_pass() {
+        let mut actual = gradient();
+        actual.get_pixel_mut(1, 1).0[2] = 103;
+        let comparison = compare(&actual, &gradient(), 3);
+        assert!(comparison.passed());
+        assert_eq!(comparison.max_difference, 3);
+    }
+
+    #[test]
+    fn differences_above_tolerance_are_red() {
+        let mut actual = gradient();
+        actual.get_pixel_mut(1, 1).0[0] = 0;
+        actual.get_pixel_mut(5, 3).0[3] = 200;
+        let comparison = compare(&actual, &gradient(), 3);
+        assert!(!comparison.passed());
+        assert_eq!(comparison.mismatched, 2);
+        assert_eq!(comparison.max_difference, 55);
+        assert_eq!(*comparison.diff.get_pixel(1, 1), Rgba([255, 0, 0, 255]));
+        assert_eq!(*comparison.diff.get_pixel(5, 3), Rgba([255, 0, 0, 255]));
+        assert_ne!(*comparison.diff.get_pixel(2, 1), Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn different_sizes_mismatch_everywhere() {
+        let comparison = compare(&RgbaImage::new(4, 4), &gradient(), 255);
+        assert!(!comparison.passed());
+        assert_eq!(comparison.mismatched, 32);
+        assert_eq!(comparison.diff.dimensions(), (8, 4));
+        assert!(comparison.diff.pixels().all(|x| *x == Rgba([255, 0, 0, 255])));
+    }
+}
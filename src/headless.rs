@@ -0,0 +1,108 @@
+//! A gl 4.5 core context without any window or display server, for the tests and `--headless`.
+//!
+//! Uses an EGL surfaceless display (`EGL_MESA_platform_surfaceless`), which Mesa provides
+//! on every linux box including the gpu-less ones through llvmpipe. Rendering only works
+//! into framebuffer objects, there is no default framebuffer.
+
+use std::ffi::{c_char, c_void, CString};
+use std::ptr;
+use std::sync::Once;
+
+use crate::error::{Error, Result};
+
+type EglDisplay = *mut c_void;
+type EglContext = *mut c_void;
+type EglBoolean = u32;
+type EglInt = i32;
+type GetPlatformDisplay = unsafe extern "C" fn(u32, *mut c_void, *const EglInt) -> EglDisplay;
+
+const EGL_NONE: EglInt = 0x3038;
+const EGL_OPENGL_API: u32 = 0x30A2;
+const EGL_PLATFORM_SURFACELESS_MESA: u32 = 0x31DD;
+const EGL_CONTEXT_MAJOR_VERSION: EglInt = 0x3098;
+const EGL_CONTEXT_MINOR_VERSION: EglInt = 0x30FB;
+const EGL_CONTEXT_OPENGL_PROFILE_MASK: EglInt = 0x30FD;
+const EGL_CONTEXT_OPENGL_CORE_PROFILE_BIT: EglInt = 0x1;
+
+#[link(name = "EGL")]
+extern "C" {
+    fn eglGetProcAddress(name: *const c_char) -> *const c_void;
+    fn eglInitialize(display: EglDisplay, major: *mut EglInt, minor: *mut EglInt) -> EglBoolean;
+    fn eglBindAPI(api: u32) -> EglBoolean;
+    fn eglCreateContext(display: EglDisplay, config: *mut c_void, share: EglContext,
+                        attributes: *const EglInt) -> EglContext;
+    fn eglMakeCurrent(display: EglDisplay, draw: *mut c_void, read: *mut c_void,
+                      context: EglContext) -> EglBoolean;
+    fn eglDestroyContext(display: EglDisplay, context: EglContext) -> EglBoolean;
+    fn eglGetError() -> EglInt;
+}
+
+static LOAD_GL: Once = Once::new();
+
+/// Current on the thread that created it until dropped.
+/// The display is shared by every context of the process and never terminated.
+pub struct HeadlessContext {
+    display: EglDisplay,
+    context: EglContext,
+}
+
+impl HeadlessContext {
+    pub fn new() -> Result<HeadlessContext> {
+        unsafe {
+            let get_platform_display = proc_address("eglGetPlatformDisplayEXT");
+            if get_platform_display.is_null() {
+                return Err(Error::Context("EGL_EXT_platform_base is not supported".to_string()));
+            }
+            let get_platform_display: GetPlatformDisplay = std::mem::transmute(get_platform_display);
+            let display = get_platform_display(EGL_PLATFORM_SURFACELESS_MESA, ptr::null_mut(), ptr::null());
+            if display.is_null() {
+                return Err(egl_error("no surfaceless display"));
+            }
+            if eglInitialize(display, ptr::null_mut(), ptr::null_mut()) == 0 {
+                return Err(egl_error("eglInitialize failed"));
+            }
+            if eglBindAPI(EGL_OPENGL_API) == 0 {
+                return Err(egl_error("desktop gl is not supported"));
+            }
+
+            let attributes = [
+                EGL_CONTEXT_MAJOR_VERSION, 4,
+                EGL_CONTEXT_MINOR_VERSION, 5,
+                EGL_CONTEXT_OPENGL_PROFILE_MASK, EGL_CONTEXT_OPENGL_CORE_PROFILE_BIT,
+                EGL_NONE,
+            ];
+            // surfaceless has no configs, EGL_KHR_no_config_context lets us go without one
+            let context = eglCreateContext(display, ptr::null_mut(), ptr::null_mut(), attributes.as_ptr());
+            if context.is_null() {
+                return Err(egl_error("no 4.5 core profile context"));
+            }
+            if eglMakeCurrent(display, ptr::null_mut(), ptr::null_mut(), context) == 0 {
+                eglDestroyContext(display, context);
+                return Err(egl_error("eglMakeCurrent failed"));
+            }
+
+            // the function pointers are the same for every context of the display
+            LOAD_GL.call_once(|| gl::load_with(proc_address));
+            Ok(HeadlessContext { display, context })
+        }
+    }
+}
+
+impl Drop for HeadlessContext {
+    fn drop(&mut self) {
+        unsafe {
+            eglMakeCurrent(self.display, ptr::null_mut(), ptr::null_mut(), ptr::null_mut());
+            eglDestroyContext(self.display, self.context);
+        }
+    }
+}
+
+fn proc_address(name: &str) -> *const c_void {
+    let name = CString::new(name).unwrap();
+    unsafe { eglGetProcAddress(name.as_ptr()) }
+}
+
+fn egl_error(message: &str) -> Error {
+    let code = unsafe { eglGetError() };
+    Error::Context(format!("{} (EGL error 0x{:X})", message, code))
+}
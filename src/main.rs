@@ -2,9 +2,8 @@ extern crate core;
 extern crate gl;
 extern crate glfw;
 
-use std::{ptr, thread};
-use std::sync::{Arc, mpsc, Mutex};
-use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 use crate::alignment::Attribute::{ Position, UV};
 use crate::rendering::RenderPath;
 use crate::textures::{Texture2D};
@@ -14,46 +13,98 @@ mod model;
 mod alignment;
 mod glfw_bind;
 mod textures;
+#[allow(dead_code, unused_imports)]
 mod dispatcher;
 mod buffer;
 mod rendering;
 mod camera;
 mod scene;
 mod computation;
+#[cfg(test)]
+mod golden;
+mod headless;
+mod error;
+mod reload;
+mod reflection;
+mod geometry;
+mod material;
+mod assets;
+mod present;
+mod sampler;
+mod skybox;
+mod viewport;
+mod raycast;
 
 
 
 
 fn main() {
-    let mut path = RenderPath::new(1200, 720);
-    start(&mut path);
+    let mut path = create_or_exit(match headless_frames() {
+        Some(frames) => RenderPath::headless(1200, 720, frames),
+        None => RenderPath::new(1200, 720)
+    });
+    let model = model_path();
+    start(&mut path, &model);
 }
 
-fn start(renderer: &mut RenderPath) {
-    renderer.start();
+const DEFAULT_MODELS: [&str; 2] = ["res/model/sponza/sponza.obj", "res/model/teapot.obj"];
+
+/// `--model <path>`, otherwise sponza if it is there and the teapot if not
+fn model_path() -> PathBuf {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--model" {
+            if let Some(path) = args.next() {
+                return PathBuf::from(path);
+            }
+        }
+    }
+    DEFAULT_MODELS.iter()
+                  .map(PathBuf::from)
+                  .find(|x| x.exists())
+                  .unwrap_or_else(|| PathBuf::from(DEFAULT_MODELS[0]))
+}
+
+/// `--headless <frames>` renders offscreen and exits after the given amount of frames
+fn headless_frames() -> Option<u32> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--headless" {
+            let frames = args.next().and_then(|x| x.parse().ok()).unwrap_or(1);
+            return Some(frames);
+        }
+    }
+    None
+}
+
+fn create_or_exit(path: error::Result<RenderPath>) -> RenderPath {
+    path.unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    })
+}
+
+fn start(renderer: &mut RenderPath, model: &Path) {
+    renderer.start(model);
     let mut frames = 0;
     let mut fps_timer = Instant::now();
     while renderer.running() {
         let i = fps_timer.elapsed().as_millis();
         if i >= 1000 {
-            renderer.window.fps = frames;
+            renderer.fps = frames;
             fps_timer = Instant::now();
             frames = 0;
         }
         frames += 1;
 
 
-        renderer.window.glfw.poll_events();
-        let mut events = Vec::new();
-        for x in glfw::flush_messages(&renderer.window.event_receiver) {
-            events.push(x.1);
-        }
-        for event in events.iter() {
-            renderer.handle_event(&event);
-            renderer.ui.handle_event(&mut renderer.ui_context, &event);
-        }
+        renderer.poll_events();
 
         renderer.render();
     }
+    // headless runs have no ui to show it in
+    if let Some(error) = renderer.error().filter(|_| renderer.window().is_none()) {
+        eprintln!("{}", error);
+    }
     renderer.dispose();
 }
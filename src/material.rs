@@ -0,0 +1,257 @@
+use std::fs;
+use std::rc::Rc;
+use std::path::{Component, Path, PathBuf};
+
+use glam::Vec3;
+
+use crate::assets::TextureCache;
+use crate::error::{Error, Result};
+use crate::sampler::{Sampler, SamplerDesc};
+use crate::shader::Shader;
+use crate::textures::{ColorSpace, State, Texture2D};
+
+pub const DIFFUSE_UNIT: u32 = 0;
+pub const NORMAL_UNIT: u32 = 1;
+pub const SPECULAR_UNIT: u32 = 2;
+pub const ALPHA_UNIT: u32 = 3;
+
+/// Everything of a mtl material that is used for shading.
+/// The normal slot holds `map_bump`/`bump`, which is a height map for sponza.
+pub struct Material {
+    pub name: String,
+    pub diffuse: Vec3,
+    pub specular: Vec3,
+    pub shininess: f32,
+    pub opacity: f32,
+    pub diffuse_texture: Option<Rc<Texture2D>>,
+    pub normal_texture: Option<Rc<Texture2D>>,
+    pub specular_texture: Option<Rc<Texture2D>>,
+    pub alpha_texture: Option<Rc<Texture2D>>,
+    /// shared by all four maps, `None` for materials without textures
+    pub sampler: Option<Sampler>,
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material {
+            name: String::from("default"),
+            diffuse: Vec3::splat(0.8),
+            specular: Vec3::ZERO,
+            shininess: 1.0,
+            opacity: 1.0,
+            diffuse_texture: None,
+            normal_texture: None,
+            specular_texture: None,
+            alpha_texture: None,
+            sampler: None,
+        }
+    }
+}
+
+impl Material {
+    /// Texture names are resolved relative to `directory`, the folder of the obj/mtl file.
+    pub fn from_tobj(material: &tobj::Material, directory: &Path, cache: &mut TextureCache) -> Result<Material> {
+        // most exporters write the albedo into both map_Ka and map_Kd
+        let diffuse_name = if material.diffuse_texture.is_empty() {
+            &material.ambient_texture
+        } else {
+            &material.diffuse_texture
+        };
+        let mut result = Material {
+            name: material.name.clone(),
+            diffuse: Vec3::from(material.diffuse),
+            specular: Vec3::from(material.specular),
+            shininess: material.shininess,
+            opacity: material.dissolve,
+            diffuse_texture: load_texture(cache, directory, diffuse_name, ColorSpace::Srgb)?,
+            normal_texture: load_texture(cache, directory, &material.normal_texture,
+                                         ColorSpace::Linear)?,
+            specular_texture: load_texture(cache, directory, &material.specular_texture,
+                                           ColorSpace::Srgb)?,
+            alpha_texture: load_texture(cache, directory, &material.dissolve_texture,
+                                        ColorSpace::Linear)?,
+            sampler: None,
+        };
+        if result.has_textures() {
+            result.sampler = Some(Sampler::new(SamplerDesc::anisotropic()));
+        }
+        Ok(result)
+    }
+
+    pub fn has_textures(&self) -> bool {
+        self.diffuse_texture.is_some() || self.normal_texture.is_some() ||
+            self.specular_texture.is_some() || self.alpha_texture.is_some()
+    }
+
+    /// Binds the textures to their units, without touching any uniforms.
+    pub fn bind_textures(&self) {
+        let slots = [
+            (DIFFUSE_UNIT, &self.diffuse_texture),
+            (NORMAL_UNIT, &self.normal_texture),
+            (SPECULAR_UNIT, &self.specular_texture),
+            (ALPHA_UNIT, &self.alpha_texture),
+        ];
+        for (unit, texture) in slots.iter() {
+            unsafe {
+                gl::ActiveTexture(gl::TEXTURE0 + unit);
+            }
+            match texture {
+                Some(texture) => texture.bind(),
+                None => unsafe {
+                    gl::BindTexture(gl::TEXTURE_2D, 0);
+                }
+            }
+            match &self.sampler {
+                Some(sampler) => sampler.bind(*unit),
+                None => Sampler::unbind(*unit),
+            }
+        }
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0);
+        }
+    }
+
+    /// Resets every material unit, so samplers don't leak into later passes like the ui.
+    pub fn unbind_textures() {
+        for unit in [DIFFUSE_UNIT, NORMAL_UNIT, SPECULAR_UNIT, ALPHA_UNIT] {
+            Sampler::unbind(unit);
+            unsafe {
+                gl::ActiveTexture(gl::TEXTURE0 + unit);
+                gl::BindTexture(gl::TEXTURE_2D, 0);
+            }
+        }
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0);
+        }
+    }
+
+    /// Binds the textures and uploads the factors to the `material*` uniforms of the shader.
+    pub fn bind(&self, shader: &Shader) {
+        self.bind_textures();
+        // mtl colors are picked in srgb, the shader works in linear space
+        shader.load_vec3("materialDiffuse", &srgb_to_linear(self.diffuse));
+        shader.load_vec3("materialSpecular", &srgb_to_linear(self.specular));
+        shader.load_float("materialShininess", self.shininess);
+        shader.load_float("materialOpacity", self.opacity);
+
+        shader.load_sampler("diffuseMap", DIFFUSE_UNIT);
+        shader.load_sampler("normalMap", NORMAL_UNIT);
+        shader.load_sampler("specularMap", SPECULAR_UNIT);
+        shader.load_sampler("alphaMap", ALPHA_UNIT);
+        shader.load_int("hasDiffuseMap", self.diffuse_texture.is_some() as i32);
+        shader.load_int("hasNormalMap", self.normal_texture.is_some() as i32);
+        shader.load_int("hasSpecularMap", self.specular_texture.is_some() as i32);
+        shader.load_int("hasAlphaMap", self.alpha_texture.is_some() as i32);
+    }
+}
+
+pub fn srgb_to_linear(color: Vec3) -> Vec3 {
+    let channel = |x: f32| {
+        if x <= 0.04045 {
+            x / 12.92
+        } else {
+            ((x + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    Vec3::new(channel(color.x), channel(color.y), channel(color.z))
+}
+
+fn load_texture(cache: &mut TextureCache, directory: &Path, name: &str,
+                color_space: ColorSpace) -> Result<Option<Rc<Texture2D>>> {
+    if name.trim().is_empty() {
+        return Ok(None);
+    }
+    match resolve_path(directory, name) {
+        // a broken file is as good as a missing one, it shouldn't take the whole model down
+        Some(path) => match cache.load(&path, color_space) {
+            Err(Error::ImageDecode { .. }) => Ok(Some(cache.fallback_for(path, color_space))),
+            result => result.map(Some),
+        },
+        None => Ok(Some(cache.fallback_for(directory.join(name.trim()), color_space))),
+    }
+}
+
+/// Resolves a texture name from a mtl file relative to `directory`.
+/// Backslashes are treated as separators and if the exact path does not exist,
+/// every component is matched case-insensitively, since exporters on windows
+/// don't care about the case (sponza references `SP_LUK.JPG` for `sp_luk.JPG`).
+pub fn resolve_path(directory: &Path, name: &str) -> Option<PathBuf> {
+    let normalized = name.trim().replace('\\', "/");
+    let relative = Path::new(normalized.as_str());
+    let exact = directory.join(relative);
+    if exact.is_file() {
+        return Some(exact);
+    }
+
+    let mut current = directory.to_path_buf();
+    for component in relative.components() {
+        let part = match component {
+            Component::Normal(part) => part.to_str()?,
+            Component::ParentDir => {
+                current.push("..");
+                continue;
+            }
+            Component::CurDir => continue,
+            _ => return None,
+        };
+        let exact = current.join(part);
+        if exact.exists() {
+            current = exact;
+            continue;
+        }
+        let entry = fs::read_dir(&current).ok()?
+                                          .filter_map(|x| x.ok())
+                                          .find(|x| x.file_name()
+                                                     .to_str()
+                                                     .is_some_and(|x| x.eq_ignore_ascii_case(part)))?;
+        current = entry.path();
+    }
+    if current.is_file() {
+        Some(current)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::HeadlessContext;
+
+    const SPONZA: &str = "res/model/sponza";
+
+    #[test]
+    fn resolve_ignores_case() {
+        let directory = Path::new(SPONZA);
+        assert_eq!(resolve_path(directory, "SP_LUK.JPG"), Some(directory.join("sp_luk.JPG")));
+        assert_eq!(resolve_path(directory, " sp_luk.jpg "), Some(directory.join("sp_luk.JPG")));
+        assert_eq!(resolve_path(directory, "sp_luk.png"), None);
+        // directories are files only at the end
+        assert_eq!(resolve_path(Path::new("res/model"), "SPONZA"), None);
+    }
+
+    #[test]
+    fn resolve_backslashes() {
+        let expected = Path::new(SPONZA).join("sp_luk.JPG");
+        assert_eq!(resolve_path(Path::new("res"), "model\\sponza\\sp_luk.JPG"), Some(expected.clone()));
+        assert_eq!(resolve_path(Path::new("res"), "Model\\Sponza\\SP_LUK.JPG"), Some(expected));
+        let parent = resolve_path(Path::new("res/textures"), "..\\model\\sponza\\SP_LUK.JPG").unwrap();
+        assert_eq!(fs::canonicalize(parent).unwrap(), fs::canonicalize(SPONZA).unwrap().join("sp_luk.JPG"));
+    }
+
+    #[test]
+    fn broken_texture_falls_back() {
+        let _context = HeadlessContext::new()
+            .unwrap_or_else(|e| panic!("needs a surfaceless EGL context: {}", e));
+        let directory = std::env::temp_dir().join("broken_texture_falls_back");
+        fs::create_dir_all(&directory).unwrap();
+        let broken = directory.join("broken.png");
+        fs::write(&broken, b"not a png").unwrap();
+
+        let mut cache = TextureCache::new();
+        let texture = load_texture(&mut cache, &directory, "broken.png", ColorSpace::Srgb).unwrap();
+        assert!(Rc::ptr_eq(&texture.unwrap(), &cache.fallback(ColorSpace::Srgb)));
+        assert_eq!(cache.missing(), [broken]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
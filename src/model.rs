@@ -1,20 +1,31 @@
 use std::{mem, ptr};
+use std::cell::OnceCell;
 use std::ffi::c_void;
 use std::path::Path;
 
-use gl::types::{GLboolean, GLfloat, GLint, GLsizei, GLsizeiptr};
-use glam::{Vec2, Vec3, Vec4};
-use tobj::Material;
+use gl::types::{GLsizei, GLsizeiptr};
+use glam::{Vec2, Vec3};
 
-use crate::{Position, Texture2D, UV};
-use crate::alignment::{Attribute, Layout};
-use crate::alignment::Attribute::Color;
-use crate::textures::State;
+use crate::{Position, UV};
+use crate::alignment::{pack_half2, pack_snorm_2_10_10_10, Attribute, Mode, VertexFormat};
+use crate::vertex_format;
+use crate::alignment::Attribute::{Color, PackedNormal, Tangent, UVHalf};
+use crate::error::{Error, Result};
+use crate::assets::TextureCache;
+use crate::material::Material;
+use crate::shader::Shader;
+use crate::geometry::{generate_normals, generate_tangents, Aabb, BoundingSphere, Ray};
+use crate::raycast::{Bvh, Hit, Triangle};
 
 pub struct Mesh<V> {
     vertices: Vec<V>,
     indices: Vec<u32>,
-    textures: Option<Texture2D>,
+    /// object name from the obj file, empty for meshes built from lists
+    pub name: String,
+    bounds: Aabb,
+    sphere: BoundingSphere,
+    /// index into `Model::materials`
+    pub material: Option<usize>,
     objects_count: i32,
     vertex_array_object: u32,
     vertex_buffer_object: u32,
@@ -23,11 +34,42 @@ pub struct Mesh<V> {
 
 pub struct Model<V> {
     pub meshes: Vec<Mesh<V>>,
+    pub materials: Vec<Material>,
+    bounds: Aabb,
+    sphere: BoundingSphere,
+    /// built on the first ray cast
+    bvh: OnceCell<Bvh>,
+}
+
+/// Vertex types with a position, needed to ray cast against a model.
+pub trait VertexPosition {
+    fn position(&self) -> Vec3;
+}
+
+pub type Materials = std::result::Result<Vec<tobj::Material>, tobj::LoadError>;
+
+/// The materials are returned separately since untextured models don't need them.
+pub fn load_obj(obj_file: &Path) -> Result<(Vec<tobj::Model>, Materials)> {
+    if !obj_file.exists() {
+        return Err(Error::not_found(obj_file));
+    }
+    tobj::load_obj(
+        obj_file,
+        &tobj::LoadOptions {
+            triangulate: true,
+            ignore_points: true,
+            single_index: true,
+            ignore_lines: true,
+        },
+    ).map_err(|e| Error::ObjParse {
+        path: obj_file.to_path_buf(),
+        source: e,
+    })
 }
 
 
 impl Model<Vertex> {
-    pub fn from_tobj(models: Vec<tobj::Model>) -> Model<Vertex> {
+    pub fn from_tobj(models: Vec<tobj::Model>) -> Result<Model<Vertex>> {
         let mut meshes: Vec<Mesh<Vertex>> = Vec::new();
         for model in models.iter() {
             let mut vertices: Vec<Vertex> = Vec::new();
@@ -45,44 +87,106 @@ impl Model<Vertex> {
                 i += 3;
             }
 
-            meshes.push(Mesh::from_lists(indices, vertices, Layout::new(vec![Position])));
+            let mut mesh = Mesh::from_lists(indices, vertices)?;
+            mesh.name = model.name.clone();
+            meshes.push(mesh);
         }
+        Ok(Model::new(meshes, Vec::new()))
+    }
+}
+
+impl<V: VertexPosition> Model<V> {
+    /// Bvh over the triangles of every mesh, in model space.
+    pub fn bvh(&self) -> &Bvh {
+        self.bvh.get_or_init(|| {
+            let mut triangles = Vec::new();
+            for (index, mesh) in self.meshes.iter().enumerate() {
+                let vertices = mesh.vertices();
+                for (triangle, corners) in mesh.indices().chunks_exact(3).enumerate() {
+                    triangles.push(Triangle {
+                        corners: [vertices[corners[0] as usize].position(),
+                                  vertices[corners[1] as usize].position(),
+                                  vertices[corners[2] as usize].position()],
+                        mesh: index,
+                        triangle,
+                    });
+                }
+            }
+            Bvh::new(triangles)
+        })
+    }
+
+    /// Closest hit of a ray given in model space
+    pub fn raycast(&self, ray: &Ray) -> Option<Hit> {
+        self.bvh().nearest(ray)
+    }
+
+    /// Every hit of a ray given in model space, sorted by distance
+    pub fn raycast_all(&self, ray: &Ray) -> Vec<Hit> {
+        self.bvh().all(ray)
+    }
+}
+
+impl<V> Model<V> {
+    /// Bounds of the model are the union of the mesh bounds.
+    /// Meshes without vertices are left out, their zero sphere at the origin would stretch the rest.
+    pub fn new(meshes: Vec<Mesh<V>>, materials: Vec<Material>) -> Model<V> {
+        let bounds = meshes.iter().fold(Aabb::empty(), |bounds, x| bounds.union(x.bounds));
+        let sphere = meshes.iter()
+                           .filter(|x| !x.bounds.is_empty())
+                           .map(|x| x.sphere)
+                           .reduce(BoundingSphere::union)
+                           .unwrap_or(BoundingSphere { center: Vec3::ZERO, radius: 0.0 });
         Model {
-            meshes
+            meshes,
+            materials,
+            bounds,
+            sphere,
+            bvh: OnceCell::new(),
         }
     }
+
+    /// In model space, use `Aabb::transform` with the object transform for world space
+    pub fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        self.sphere
+    }
 }
 
 impl <T> Model<T> {
-    pub fn render(&self) {
-        unsafe {
-            for x in self.meshes.iter() {
-                x.render();
-            }
+    /// Binds the material of each mesh, textures and factors, to the shader and writes
+    /// `id(mesh index)` to its `objectId` uniform for picking.
+    /// Meshes without a material use `Material::default()`.
+    pub fn render_with_ids(&self, shader: &Shader, id: impl Fn(usize) -> u32) {
+        let default = Material::default();
+        for (index, x) in self.meshes.iter().enumerate() {
+            let material = x.material.and_then(|x| self.materials.get(x)).unwrap_or(&default);
+            material.bind(shader);
+            shader.load_uint("objectId", id(index));
+            x.render();
         }
+        Material::unbind_textures();
     }
+}
 
-    pub fn delete(&self) {
-        for x in self.meshes.iter() {
-            x.delete();
-        }
+vertex_format! {
+    pub struct Vertex {
+        pub position: Vec3 => Position,
     }
 }
 
-#[repr(C)]
-pub struct Vertex {
-    pub position: Vec3,
+impl VertexPosition for Vertex {
+    fn position(&self) -> Vec3 {
+        self.position
+    }
 }
 
 impl<V> Mesh<V> {
     pub fn render(&self) {
         unsafe {
-            if let Some(texture) = &self.textures {
-                // println!("bound {}", texture.id);
-                texture.bind();
-            }
-            gl::ActiveTexture(gl::TEXTURE0);
-
             gl::BindVertexArray(self.vertex_array_object);
             gl::DrawElements(gl::TRIANGLES,
                              self.objects_count,
@@ -90,15 +194,61 @@ impl<V> Mesh<V> {
                              ptr::null());
         }
     }
-    pub fn delete(&self) {
+
+}
+
+impl<V> Drop for Mesh<V> {
+    fn drop(&mut self) {
         unsafe {
             gl::DeleteVertexArrays(1, &self.vertex_array_object);
             gl::DeleteBuffers(1, &self.vertex_buffer_object);
             gl::DeleteBuffers(1, &self.element_buffer_object);
         }
     }
+}
+
+impl<V> Mesh<V> {
+    /// The cpu copy of the uploaded vertices
+    pub fn vertices(&self) -> &[V] {
+        &self.vertices
+    }
+
+    pub fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        self.sphere
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.vertices.len()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+}
+
+impl<V: VertexFormat + VertexPosition> Mesh<V> {
+    /// Uploads the lists and computes the bounds from the cpu copy.
+    /// `vertex_format!` checks the layout at compile time, hand written layouts only here.
+    pub fn from_lists(indices: Vec<u32>, vertices: Vec<V>) -> Result<Mesh<V>> {
+        let layout = V::layout();
+        if layout.vertex_size() as usize != mem::size_of::<V>() {
+            return Err(Error::LayoutMismatch(format!(
+                "layout is {} bytes, but {} is {} bytes", layout.vertex_size(),
+                std::any::type_name::<V>(), mem::size_of::<V>())));
+        }
+        layout.check_locations()?;
+        let positions: Vec<Vec3> = vertices.iter().map(|x| x.position()).collect();
+        let bounds = Aabb::from_points(positions.iter().copied());
+        let sphere = BoundingSphere::from_points(&positions);
 
-    pub fn from_lists(indices: Vec<u32>, vertices: Vec<V>, layout: Layout) -> Mesh<V> {
         let mut vao = 0;
         let mut vbo = 0;
         let mut ebo = 0;
@@ -127,72 +277,74 @@ impl<V> Mesh<V> {
 
             let mut offset = 0;
             for (i, attrib) in layout.attributes.iter().enumerate() {
-                gl::VertexAttribPointer(
-                    i as u32,
-                    attrib.size() as i32,
-                    attrib.gl_type(),
-                    gl::FALSE,
-                    vertex_size as GLsizei,
-                    offset as *const c_void,
-                );
+                let location = layout.location(i);
+                if attrib.mode() == Mode::Integer {
+                    gl::VertexAttribIPointer(
+                        location,
+                        attrib.size() as i32,
+                        attrib.gl_type(),
+                        vertex_size as GLsizei,
+                        offset as *const c_void,
+                    );
+                } else {
+                    gl::VertexAttribPointer(
+                        location,
+                        attrib.size() as i32,
+                        attrib.gl_type(),
+                        attrib.normalized(),
+                        vertex_size as GLsizei,
+                        offset as *const c_void,
+                    );
+                }
                 offset += attrib.size_bytes();
-                gl::EnableVertexAttribArray(i as u32);
+                gl::EnableVertexAttribArray(location);
             }
 
             gl::BindBuffer(gl::ARRAY_BUFFER, 0);
             gl::BindVertexArray(0);
         }
-        Mesh {
+        Ok(Mesh {
             objects_count: indices.len() as i32,
             indices,
             vertices,
             vertex_array_object: vao,
             vertex_buffer_object: vbo,
             element_buffer_object: ebo,
-            textures: None,
-        }
+            name: String::new(),
+            bounds,
+            sphere,
+            material: None,
+        })
     }
 }
 
-#[repr(C)]
-pub struct TexVertex {
-    position: Vec3,
-    color: Vec3,
-    uv: Vec2,
+vertex_format! {
+    pub struct TexVertex {
+        position: Vec3 => Position,
+        color: Vec3 => Color,
+        uv: Vec2 => UV,
+    }
 }
 
+impl VertexPosition for TexVertex {
+    fn position(&self) -> Vec3 {
+        self.position
+    }
+}
+
+fn load_materials(materials: &[tobj::Material], directory: &Path, cache: &mut TextureCache) -> Result<Vec<Material>> {
+    materials.iter().map(|x| Material::from_tobj(x, directory, cache)).collect()
+}
 
 impl Model<TexVertex> {
-    pub fn from_textured_tobj(models: Vec<tobj::Model>, materials: Vec<Material>) -> Model<TexVertex> {
-        let mut textures = Vec::new();
-        for x in materials.iter() {
-            let string = format!("res/model/sponza/{}", x.ambient_texture);
-            let text_path = Path::new(string.as_str());
-            if !text_path.exists() {
-                panic!("path not existent");
-            }
-            println!("loading {:?}",text_path.to_str());
-            if !text_path.is_file() {
-                textures.push(Texture2D {
-                    id: 0,
-                    width: 0,
-                    height: 0
-                });
-                continue;
-            }
-            let img = image::open(&Path::new(text_path.to_str().unwrap())).expect("Failed to load \
-            texture");
-            let img = img.flipv();
-            let data = img.as_bytes();
-            let texture = Texture2D::from_rgb(data, img.width(), img.height());
-            textures.push(texture);
-            // println!("Material {} , {}", x.ambient_texture, text_path.exists());
-        }
+    /// `directory` is the folder the textures of the materials are relative to,
+    /// usually the one containing the obj file.
+    pub fn from_textured_tobj(models: Vec<tobj::Model>, materials: Vec<tobj::Material>, directory: &Path, cache: &mut TextureCache) -> Result<Model<TexVertex>> {
+        let materials = load_materials(&materials, directory, cache)?;
 
         let mut meshes: Vec<Mesh<TexVertex>> = Vec::new();
-        'model: for model in models.iter() {
-            let index = model.mesh.material_id.expect("partial uv not supported");
-            let mat = textures.get(index).expect("could not find material");
+        for model in models.iter() {
+            let index = model.mesh.material_id.filter(|x| *x < materials.len());
 
             let mut vertices: Vec<TexVertex> = Vec::new();
             let mut indices: Vec<u32> = Vec::new();
@@ -205,30 +357,10 @@ impl Model<TexVertex> {
             let mut i = 0;
             let mut texture_index = 0;
 
-            println!("Verts {}", model.mesh.positions.len());
-            println!("texts {}", model.mesh.texcoords.len());
-
             while i < len {
-                let x1 = model.mesh.texcoords.get(texture_index);
-                let y1 = model.mesh.texcoords.get(texture_index + 1);
-
-                // if x1.is_none() || y1.is_none() {
-                //     continue 'model;
-                // }
-
-                let val_x;
-                if x1.is_none() {
-                    val_x = 0.0f32;
-                } else {
-                    val_x = *x1.unwrap();
-                }
-
-                let val_y;
-                if y1.is_none() {
-                    val_y = 0.0f32;
-                } else {
-                    val_y = *y1.unwrap();
-                }
+                // meshes without uvs still load, every vertex gets 0, 0
+                let val_x = model.mesh.texcoords.get(texture_index).copied().unwrap_or(0.0);
+                let val_y = model.mesh.texcoords.get(texture_index + 1).copied().unwrap_or(0.0);
 
                 vertices.push(TexVertex {
                     position: Vec3::new(model.mesh.positions[i], model.mesh.positions[i + 1],
@@ -240,17 +372,170 @@ impl Model<TexVertex> {
                 i += 3;
             }
 
-            let mut mesh = Mesh::from_lists(indices, vertices, Layout::new(vec![Position, Color,
-                                                                                UV]));
-            mesh.textures = Some(Texture2D {
-                id: mat.id,
-                width: mat.width,
-                height: mat.height
-            });
+            let mut mesh = Mesh::from_lists(indices, vertices)?;
+            mesh.name = model.name.clone();
+            mesh.material = index;
             meshes.push(mesh);
         }
-        Model {
-            meshes
+        Ok(Model::new(meshes, materials))
+    }
+}
+
+vertex_format! {
+    pub struct LitVertex {
+        pub position: Vec3 => Position,
+        /// see `pack_snorm_2_10_10_10`
+        pub normal: u32 => PackedNormal,
+        /// see `pack_half2`
+        pub uv: [u16; 2] => UVHalf,
+        /// bitangent is `cross(normal, tangent.xyz) * tangent.w`
+        pub tangent: [f32; 4] => Tangent,
+    }
+}
+
+impl VertexPosition for LitVertex {
+    fn position(&self) -> Vec3 {
+        self.position
+    }
+}
+
+impl Model<LitVertex> {
+    /// Uses the normals of the obj if it has them, otherwise they are generated
+    /// with `smoothing_angle`, see `generate_normals`.
+    pub fn from_lit_tobj(models: Vec<tobj::Model>, materials: Vec<tobj::Material>, directory: &Path, cache: &mut TextureCache, smoothing_angle: f32) -> Result<Model<LitVertex>> {
+        let materials = load_materials(&materials, directory, cache)?;
+
+        let mut meshes: Vec<Mesh<LitVertex>> = Vec::new();
+        for model in models.iter() {
+            let mesh = &model.mesh;
+            let index = mesh.material_id.filter(|x| *x < materials.len());
+
+            let positions: Vec<Vec3> = mesh.positions.chunks_exact(3)
+                                           .map(|x| Vec3::new(x[0], x[1], x[2]))
+                                           .collect();
+            let uv_at = |i: usize| Vec2::new(
+                mesh.texcoords.get(i * 2).copied().unwrap_or(0.0),
+                mesh.texcoords.get(i * 2 + 1).copied().unwrap_or(0.0));
+
+            let (positions, normals, uvs, indices): (Vec<Vec3>, Vec<Vec3>, Vec<Vec2>, Vec<u32>) = if mesh.normals.len() == mesh.positions.len() {
+                let normals = mesh.normals.chunks_exact(3)
+                                  .map(|x| Vec3::new(x[0], x[1], x[2]).normalize_or_zero())
+                                  .collect();
+                let uvs = (0..positions.len()).map(uv_at).collect();
+                (positions, normals, uvs, mesh.indices.clone())
+            } else {
+                let (vertices, indices) = generate_normals(&positions, &mesh.indices, smoothing_angle);
+                let normals = vertices.iter().map(|x| x.1).collect();
+                let uvs = vertices.iter().map(|x| uv_at(x.0 as usize)).collect();
+                let positions = vertices.iter().map(|x| positions[x.0 as usize]).collect();
+                (positions, normals, uvs, indices)
+            };
+
+            // splits the vertices on mirrored uv seams
+            let (tangents, indices) = generate_tangents(&positions, &normals, &uvs, &indices);
+
+            let vertices = tangents.iter().map(|(i, tangent)| {
+                let i = *i as usize;
+                LitVertex {
+                    position: positions[i],
+                    normal: pack_snorm_2_10_10_10(normals[i].extend(0.0)),
+                    uv: pack_half2(uvs[i]),
+                    tangent: tangent.to_array(),
+                }
+            }).collect();
+
+            let mut mesh = Mesh::from_lists(indices, vertices)?;
+            mesh.name = model.name.clone();
+            mesh.material = index;
+            meshes.push(mesh);
+        }
+        Ok(Model::new(meshes, materials))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alignment::Attribute::{BoneIndices, BoneWeights, ColorU8, Custom};
+    use crate::alignment::Component;
+    use crate::headless::HeadlessContext;
+
+    vertex_format! {
+        struct SkinnedVertex {
+            position: Vec3 => Position,
+            color: [u8; 4] => ColorU8,
+            bones: [u8; 4] => BoneIndices,
+            weights: [u8; 4] => BoneWeights,
+            material: u32 => Custom {
+                name: "Material",
+                location: 7,
+                components: 1,
+                component: Component::UInt,
+                mode: Mode::Integer,
+            },
+        }
+    }
+
+    impl VertexPosition for SkinnedVertex {
+        fn position(&self) -> Vec3 {
+            self.position
+        }
+    }
+
+    const VERTEX: &str = "#version 450 core
+layout(location = 0) in vec3 aPos;
+layout(location = 1) in vec4 aColor;
+layout(location = 2) in BONES aBones;
+layout(location = 3) in vec4 aWeights;
+layout(location = 7) in uint aMaterial;
+out vec4 color;
+flat out uint material;
+void main() {
+    color = aColor * aWeights + vec4(aBones);
+    material = aMaterial;
+    gl_Position = vec4(aPos, 1.0);
+}";
+
+    const FRAGMENT: &str = "#version 450 core
+in vec4 color;
+flat in uint material;
+out vec4 FragColor;
+void main() {
+    FragColor = color * float(material);
+}";
+
+    #[test]
+    fn integer_attributes_upload_as_integers() {
+        let _context = HeadlessContext::new()
+            .unwrap_or_else(|e| panic!("needs a surfaceless EGL context: {}", e));
+        let layout = SkinnedVertex::layout();
+
+        let shader = Shader::from_sources(&VERTEX.replace("BONES", "uvec4"), FRAGMENT).unwrap();
+        shader.reflect().validate_layout(&layout).unwrap();
+        // bone indices read as floats would be garbage
+        let shader = Shader::from_sources(&VERTEX.replace("BONES", "vec4"), FRAGMENT).unwrap();
+        assert!(matches!(shader.reflect().validate_layout(&layout), Err(Error::LayoutMismatch(_))));
+
+        let vertex = |x: f32| SkinnedVertex {
+            position: Vec3::new(x, 0.0, 0.0),
+            color: [255; 4],
+            bones: [0, 1, 2, 3],
+            weights: [64; 4],
+            material: 5,
+        };
+        let mesh = Mesh::from_lists(vec![0, 1, 2], vec![vertex(0.0), vertex(1.0), vertex(2.0)]).unwrap();
+        let integer = |location: u32| unsafe {
+            let mut value = 0;
+            gl::GetVertexAttribiv(location, gl::VERTEX_ATTRIB_ARRAY_INTEGER, &mut value);
+            value != 0
+        };
+        unsafe {
+            gl::BindVertexArray(mesh.vertex_array_object);
+        }
+        assert_eq!([0, 1, 2, 3, 7].map(integer), [false, false, true, false, true]);
+        unsafe {
+            gl::BindVertexArray(0);
+            assert_eq!(gl::GetError(), gl::NO_ERROR);
         }
     }
 }
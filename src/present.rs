@@ -0,0 +1,96 @@
+use std::path::Path;
+
+use crate::buffer::RenderTarget;
+use crate::error::Result;
+use crate::reload::ReloadableShader;
+use crate::textures::{State, Texture2D, TextureFormat};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Tonemap {
+    /// values above 1 are cut off
+    Clamp,
+    Reinhard,
+    Aces,
+}
+
+impl Tonemap {
+    fn index(&self) -> i32 {
+        match self {
+            Tonemap::Clamp => 0,
+            Tonemap::Reinhard => 1,
+            Tonemap::Aces => 2,
+        }
+    }
+}
+
+/// The output transform from the linear hdr scene target to something displayable.
+/// Applies exposure and the tonemap, encodes to srgb and writes into an 8 bit target,
+/// which is what the ui shows and what screenshots are taken from.
+pub struct Presenter {
+    shader: ReloadableShader,
+    /// core profile needs a bound vao, even if the fullscreen triangle has no attributes
+    vao: u32,
+    pub target: RenderTarget,
+    pub exposure: f32,
+    pub tonemap: Tonemap,
+}
+
+impl Presenter {
+    pub fn new(width: u32, height: u32) -> Result<Presenter> {
+        let mut target = RenderTarget::new(width, height);
+        target.new_texture_format(gl::COLOR_ATTACHMENT0, TextureFormat::RGBA8);
+        target.finish()?;
+
+        let shader = ReloadableShader::load(Path::new("res/shader/present"))?;
+
+        let mut vao = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+        }
+
+        Ok(Presenter {
+            shader,
+            vao,
+            target,
+            exposure: 1.0,
+            tonemap: Tonemap::Aces,
+        })
+    }
+
+    pub fn shader_error(&self) -> Option<&str> {
+        self.shader.error.as_deref()
+    }
+
+    pub fn present(&mut self, source: &Texture2D) {
+        self.shader.poll();
+        let shader = self.shader.shader();
+        self.target.bind();
+        unsafe {
+            gl::Viewport(0, 0, self.target.width() as i32, self.target.height() as i32);
+            gl::Disable(gl::DEPTH_TEST);
+            gl::Disable(gl::CULL_FACE);
+            gl::ActiveTexture(gl::TEXTURE0);
+        }
+        source.bind();
+        shader.bind();
+        shader.load_sampler("scene", 0);
+        shader.load_float("exposure", self.exposure);
+        shader.load_int("tonemap", self.tonemap.index());
+        unsafe {
+            gl::BindVertexArray(self.vao);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            gl::BindVertexArray(0);
+        }
+        shader.unbind();
+        source.unbind();
+        self.target.unbind();
+    }
+}
+
+impl Drop for Presenter {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
+    }
+}
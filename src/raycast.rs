@@ -0,0 +1,336 @@
+use glam::Vec3;
+
+use crate::geometry::{Aabb, Ray};
+
+const LEAF_SIZE: usize = 4;
+
+/// Where a ray hit a triangle of a model.
+#[derive(Clone, Copy, Debug)]
+pub struct Hit {
+    /// distance along the ray
+    pub t: f32,
+    pub point: Vec3,
+    /// weights of the three corners, `point = a * x + b * y + c * z`
+    pub barycentric: Vec3,
+    pub mesh: usize,
+    /// index of the triangle in the mesh, its corners are `indices[triangle * 3..][..3]`
+    pub triangle: usize,
+}
+
+#[derive(Clone, Copy)]
+pub struct Triangle {
+    pub corners: [Vec3; 3],
+    pub mesh: usize,
+    pub triangle: usize,
+}
+
+impl Triangle {
+    fn bounds(&self) -> Aabb {
+        Aabb::from_points(self.corners)
+    }
+
+    fn centroid(&self) -> Vec3 {
+        (self.corners[0] + self.corners[1] + self.corners[2]) / 3.0
+    }
+
+    fn intersect(&self, ray: &Ray, max_t: f32) -> Option<Hit> {
+        let (t, u, v) = intersect_triangle(ray, self.corners)?;
+        if t > max_t {
+            return None;
+        }
+        Some(Hit {
+            t,
+            point: ray.at(t),
+            barycentric: Vec3::new(1.0 - u - v, u, v),
+            mesh: self.mesh,
+            triangle: self.triangle,
+        })
+    }
+}
+
+/// Möller–Trumbore, both sides of the triangle count.
+/// Returns the distance and the weights of the second and third corner.
+pub fn intersect_triangle(ray: &Ray, [a, b, c]: [Vec3; 3]) -> Option<(f32, f32, f32)> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = ray.direction.cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < 1e-8 {
+        return None;
+    }
+    let inverse = 1.0 / determinant;
+    let s = ray.origin - a;
+    let u = s.dot(p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = ray.direction.dot(q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = edge2.dot(q) * inverse;
+    if t < 0.0 {
+        return None;
+    }
+    Some((t, u, v))
+}
+
+enum Node {
+    /// the second child is always at `first + 1`
+    Inner { bounds: Aabb, first: usize },
+    Leaf { bounds: Aabb, start: usize, count: usize },
+}
+
+impl Node {
+    fn bounds(&self) -> &Aabb {
+        match self {
+            Node::Inner { bounds, .. } => bounds,
+            Node::Leaf { bounds, .. } => bounds,
+        }
+    }
+}
+
+/// Bounding volume hierarchy over the triangles of a model, built on the cpu only,
+/// so it works without a gl context.
+pub struct Bvh {
+    nodes: Vec<Node>,
+    triangles: Vec<Triangle>,
+}
+
+impl Bvh {
+    /// Splits at the median of the longest axis of the triangle centroids.
+    pub fn new(mut triangles: Vec<Triangle>) -> Bvh {
+        let mut nodes = Vec::with_capacity(triangles.len() / LEAF_SIZE * 2 + 1);
+        nodes.push(Node::Leaf {
+            bounds: Aabb::empty(),
+            start: 0,
+            count: 0,
+        });
+        if !triangles.is_empty() {
+            let count = triangles.len();
+            build(&mut nodes, &mut triangles, 0, 0, count);
+        }
+        Bvh { nodes, triangles }
+    }
+
+    pub fn nearest(&self, ray: &Ray) -> Option<Hit> {
+        self.nearest_within(ray, f32::INFINITY)
+    }
+
+    /// The closest hit that is at most `max_t` along the ray.
+    pub fn nearest_within(&self, ray: &Ray, max_t: f32) -> Option<Hit> {
+        let mut best: Option<Hit> = None;
+        self.traverse(ray, max_t, |hit| {
+            if best.is_none_or(|x| hit.t < x.t) {
+                best = Some(hit);
+            }
+            hit.t
+        });
+        best
+    }
+
+    /// Every hit along the ray, sorted by distance.
+    pub fn all(&self, ray: &Ray) -> Vec<Hit> {
+        let mut hits = Vec::new();
+        self.traverse(ray, f32::INFINITY, |hit| {
+            hits.push(hit);
+            f32::INFINITY
+        });
+        hits.sort_by(|a, b| a.t.total_cmp(&b.t));
+        hits
+    }
+
+    /// Calls `on_hit` for every hit, it returns the new maximum distance to look for.
+    fn traverse(&self, ray: &Ray, max_t: f32, mut on_hit: impl FnMut(Hit) -> f32) {
+        let inverse_direction = ray.direction.recip();
+        let mut max_t = max_t;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.bounds().intersect(ray, inverse_direction, max_t).is_none() {
+                continue;
+            }
+            match node {
+                Node::Leaf { start, count, .. } => {
+                    for triangle in self.triangles[*start..*start + *count].iter() {
+                        if let Some(hit) = triangle.intersect(ray, max_t) {
+                            max_t = max_t.min(on_hit(hit));
+                        }
+                    }
+                }
+                Node::Inner { first, .. } => {
+                    // visit the closer child first, so the far one is more likely to be culled
+                    let near = |i: usize| self.nodes[i].bounds()
+                                                       .intersect(ray, inverse_direction, max_t)
+                                                       .unwrap_or(f32::INFINITY);
+                    let (a, b) = (*first, *first + 1);
+                    if near(a) <= near(b) {
+                        stack.push(b);
+                        stack.push(a);
+                    } else {
+                        stack.push(a);
+                        stack.push(b);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn build(nodes: &mut Vec<Node>, triangles: &mut [Triangle], index: usize, start: usize, count: usize) {
+    let slice = &mut triangles[start..start + count];
+    let bounds = slice.iter().fold(Aabb::empty(), |bounds, x| bounds.union(x.bounds()));
+    if count <= LEAF_SIZE {
+        nodes[index] = Node::Leaf { bounds, start, count };
+        return;
+    }
+
+    let centroids = Aabb::from_points(slice.iter().map(|x| x.centroid()));
+    let size = centroids.size();
+    let axis = if size.x >= size.y && size.x >= size.z {
+        0
+    } else if size.y >= size.z {
+        1
+    } else {
+        2
+    };
+    let half = count / 2;
+    slice.select_nth_unstable_by(half, |a, b| a.centroid()[axis].total_cmp(&b.centroid()[axis]));
+
+    let first = nodes.len();
+    for _ in 0..2 {
+        nodes.push(Node::Leaf {
+            bounds: Aabb::empty(),
+            start: 0,
+            count: 0,
+        });
+    }
+    nodes[index] = Node::Inner { bounds, first };
+    build(nodes, triangles, first, start, half);
+    build(nodes, triangles, first + 1, start + half, count - half);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CORNERS: [Vec3; 3] = [Vec3::ZERO, Vec3::X, Vec3::Y];
+
+    fn down(x: f32, y: f32) -> Ray {
+        Ray::new(Vec3::new(x, y, 1.0), Vec3::NEG_Z)
+    }
+
+    fn triangle(corners: [Vec3; 3], index: usize) -> Triangle {
+        Triangle { corners, mesh: 0, triangle: index }
+    }
+
+    #[test]
+    fn hit_and_miss() {
+        assert_eq!(intersect_triangle(&down(0.25, 0.25), CORNERS), Some((1.0, 0.25, 0.25)));
+        assert_eq!(intersect_triangle(&down(0.75, 0.75), CORNERS), None);
+        assert_eq!(intersect_triangle(&down(-0.1, 0.5), CORNERS), None);
+        assert_eq!(intersect_triangle(&down(0.5, -0.1), CORNERS), None);
+        // parallel to the plane of the triangle
+        assert_eq!(intersect_triangle(&Ray::new(Vec3::new(-1.0, 0.1, 0.0), Vec3::X), CORNERS), None);
+        // the back side counts as well
+        let up = Ray::new(Vec3::new(0.25, 0.25, -2.0), Vec3::Z);
+        assert_eq!(intersect_triangle(&up, CORNERS), Some((2.0, 0.25, 0.25)));
+    }
+
+    #[test]
+    fn edges_and_corners_hit() {
+        assert_eq!(intersect_triangle(&down(0.5, 0.0), CORNERS), Some((1.0, 0.5, 0.0)));
+        assert_eq!(intersect_triangle(&down(0.0, 0.5), CORNERS), Some((1.0, 0.0, 0.5)));
+        // on the edge from the second to the third corner, u + v == 1
+        assert_eq!(intersect_triangle(&down(0.5, 0.5), CORNERS), Some((1.0, 0.5, 0.5)));
+        assert_eq!(intersect_triangle(&down(0.0, 0.0), CORNERS), Some((1.0, 0.0, 0.0)));
+        assert_eq!(intersect_triangle(&down(1.0, 0.0), CORNERS), Some((1.0, 1.0, 0.0)));
+    }
+
+    #[test]
+    fn triangle_behind_the_origin() {
+        let ray = Ray::new(Vec3::new(0.25, 0.25, -1.0), Vec3::NEG_Z);
+        assert_eq!(intersect_triangle(&ray, CORNERS), None);
+    }
+
+    #[test]
+    fn barycentric_weights() {
+        let corners = [Vec3::new(1.0, 2.0, 0.0), Vec3::new(5.0, 2.0, 0.0), Vec3::new(1.0, 6.0, 0.0)];
+        let hit = triangle(corners, 0).intersect(&down(2.0, 4.0), f32::INFINITY).unwrap();
+        assert!((hit.barycentric - Vec3::new(0.25, 0.25, 0.5)).length() < 1e-6);
+        assert!((hit.barycentric.dot(Vec3::ONE) - 1.0).abs() < 1e-6);
+        let [a, b, c] = corners;
+        let point = a * hit.barycentric.x + b * hit.barycentric.y + c * hit.barycentric.z;
+        assert!((point - hit.point).length() < 1e-5);
+        assert!((hit.point - Vec3::new(2.0, 4.0, 0.0)).length() < 1e-5);
+    }
+
+    #[test]
+    fn nearest_of_stacked_triangles() {
+        // listed far to near, the bvh has to find the near one regardless
+        let triangles = (0..10).rev()
+                               .map(|i| triangle(CORNERS.map(|x| x - Vec3::Z * i as f32), i))
+                               .collect();
+        let bvh = Bvh::new(triangles);
+        let ray = down(0.25, 0.25);
+        let hit = bvh.nearest(&ray).unwrap();
+        assert_eq!(hit.triangle, 0);
+        assert_eq!(hit.t, 1.0);
+        assert_eq!(bvh.nearest_within(&ray, 0.5).map(|x| x.triangle), None);
+        let below = Ray::new(Vec3::new(0.25, 0.25, -3.5), Vec3::NEG_Z);
+        assert_eq!(bvh.nearest(&below).map(|x| x.triangle), Some(4));
+        assert!(Bvh::new(Vec::new()).nearest(&ray).is_none());
+    }
+
+    #[test]
+    fn all_hits_sorted_by_distance() {
+        // shuffled, so the order can't come from the input
+        let triangles = [3, 0, 7, 1, 5, 2, 9, 4, 8, 6].map(|i| triangle(CORNERS.map(|x| x - Vec3::Z * i as f32), i));
+        let bvh = Bvh::new(triangles.to_vec());
+        let hits = bvh.all(&down(0.25, 0.25));
+        assert_eq!(hits.iter().map(|x| x.triangle).collect::<Vec<_>>(), (0..10).collect::<Vec<_>>());
+        assert!(hits.windows(2).all(|x| x[0].t < x[1].t));
+        assert_eq!(hits[0].t, bvh.nearest(&down(0.25, 0.25)).unwrap().t);
+        assert!(bvh.all(&down(0.75, 0.75)).is_empty());
+        let below = Ray::new(Vec3::new(0.25, 0.25, -3.5), Vec3::NEG_Z);
+        assert_eq!(bvh.all(&below).first().map(|x| x.triangle), Some(4));
+        assert_eq!(bvh.all(&below).len(), 6);
+    }
+
+    #[test]
+    fn bvh_matches_brute_force() {
+        // xorshift, deterministic without pulling in a rand crate
+        let mut state = 0x2545f491u32;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f32 / u32::MAX as f32 * 2.0 - 1.0
+        };
+        let mut point = |scale: f32| Vec3::new(random(), random(), random()) * scale;
+
+        let triangles: Vec<Triangle> = (0..400).map(|i| {
+            let center = point(10.0);
+            triangle([center + point(1.0), center + point(1.0), center + point(1.0)], i)
+        }).collect();
+        let bvh = Bvh::new(triangles.clone());
+
+        let mut hits = 0;
+        for _ in 0..500 {
+            // aimed into the cloud, so a good share of the rays hit
+            let origin = point(15.0);
+            let ray = Ray::new(origin, point(8.0) - origin);
+            let expected = triangles.iter()
+                                    .filter_map(|x| x.intersect(&ray, f32::INFINITY))
+                                    .min_by(|a, b| a.t.total_cmp(&b.t));
+            let actual = bvh.nearest(&ray);
+            assert_eq!(actual.map(|x| x.triangle), expected.map(|x| x.triangle));
+            if let (Some(actual), Some(expected)) = (actual, expected) {
+                assert_eq!(actual.t, expected.t);
+                hits += 1;
+            }
+        }
+        assert!(hits > 100, "only {} rays hit", hits);
+    }
+}
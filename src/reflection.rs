@@ -0,0 +1,250 @@
+use std::ffi::CString;
+
+use gl::types::{GLchar, GLenum, GLint, GLuint};
+
+use crate::alignment::{Component, Layout, Mode};
+use crate::error::{Error, Result};
+
+pub struct ShaderAttribute {
+    pub name: String,
+    pub location: GLint,
+    pub size: GLint,
+    pub gl_type: GLenum,
+}
+
+pub struct ShaderUniform {
+    pub name: String,
+    /// -1 for uniforms inside of a uniform block
+    pub location: GLint,
+    pub size: GLint,
+    pub gl_type: GLenum,
+    pub block: Option<GLuint>,
+}
+
+pub struct UniformBlock {
+    pub name: String,
+    pub index: GLuint,
+    pub binding: GLint,
+    pub data_size: GLint,
+    pub uniforms: Vec<String>,
+}
+
+pub struct Reflection {
+    pub attributes: Vec<ShaderAttribute>,
+    pub uniforms: Vec<ShaderUniform>,
+    pub blocks: Vec<UniformBlock>,
+}
+
+impl Reflection {
+    pub fn new(program: GLuint) -> Reflection {
+        let mut attributes = attributes(program);
+        attributes.sort_by_key(|x| x.location);
+        Reflection {
+            attributes,
+            uniforms: uniforms(program),
+            blocks: blocks(program),
+        }
+    }
+
+    /// Checks that every vertex input of the program is fed by the attribute
+    /// at the same location in the layout, with the same amount of components.
+    pub fn validate_layout(&self, layout: &Layout) -> Result<()> {
+        for input in self.attributes.iter() {
+            let attribute = match layout.at_location(input.location as u32) {
+                Some(attribute) => attribute,
+                None => {
+                    return Err(Error::LayoutMismatch(format!(
+                        "shader input '{}' at location {} has no attribute in the layout",
+                        input.name, input.location)));
+                }
+            };
+            let expected = components(input.gl_type);
+            // the w of a packed normal may be ignored by the shader
+            let packed_xyz = attribute.component() == Component::Packed2101010 && expected == Some(3);
+            if expected != Some(attribute.size() as u32) && !packed_xyz {
+                return Err(Error::LayoutMismatch(format!(
+                    "shader input '{}' at location {} is a {}, but {} provides {} components",
+                    input.name, input.location, type_name(input.gl_type), attribute.name(),
+                    attribute.size())));
+            }
+            if is_integer(input.gl_type) != (attribute.mode() == Mode::Integer) {
+                return Err(Error::LayoutMismatch(format!(
+                    "shader input '{}' at location {} is a {}, but {} is uploaded as {:?}",
+                    input.name, input.location, type_name(input.gl_type), attribute.name(),
+                    attribute.mode())));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn attributes(program: GLuint) -> Vec<ShaderAttribute> {
+    let mut attributes = Vec::new();
+    unsafe {
+        let mut count = 0;
+        gl::GetProgramiv(program, gl::ACTIVE_ATTRIBUTES, &mut count);
+        let mut max_length = 0;
+        gl::GetProgramiv(program, gl::ACTIVE_ATTRIBUTE_MAX_LENGTH, &mut max_length);
+
+        for index in 0..count as GLuint {
+            let mut buf = vec![0u8; max_length.max(1) as usize];
+            let mut length = 0;
+            let mut size = 0;
+            let mut gl_type = 0;
+            gl::GetActiveAttrib(program, index, max_length, &mut length, &mut size,
+                                &mut gl_type, buf.as_mut_ptr() as *mut GLchar);
+            let name = to_name(buf, length);
+            if name.starts_with("gl_") {
+                continue;
+            }
+            let c_str = CString::new(name.as_str()).unwrap();
+            let location = gl::GetAttribLocation(program, c_str.as_ptr());
+            attributes.push(ShaderAttribute { name, location, size, gl_type });
+        }
+    }
+    attributes
+}
+
+fn uniforms(program: GLuint) -> Vec<ShaderUniform> {
+    let mut uniforms = Vec::new();
+    unsafe {
+        let mut count = 0;
+        gl::GetProgramiv(program, gl::ACTIVE_UNIFORMS, &mut count);
+        let mut max_length = 0;
+        gl::GetProgramiv(program, gl::ACTIVE_UNIFORM_MAX_LENGTH, &mut max_length);
+
+        for index in 0..count as GLuint {
+            let mut buf = vec![0u8; max_length.max(1) as usize];
+            let mut length = 0;
+            let mut size = 0;
+            let mut gl_type = 0;
+            gl::GetActiveUniform(program, index, max_length, &mut length, &mut size,
+                                 &mut gl_type, buf.as_mut_ptr() as *mut GLchar);
+            let name = to_name(buf, length);
+
+            let mut block_index = -1;
+            gl::GetActiveUniformsiv(program, 1, &index, gl::UNIFORM_BLOCK_INDEX, &mut block_index);
+
+            let c_str = CString::new(name.as_str()).unwrap();
+            let location = gl::GetUniformLocation(program, c_str.as_ptr());
+            uniforms.push(ShaderUniform {
+                name,
+                location,
+                size,
+                gl_type,
+                block: if block_index < 0 { None } else { Some(block_index as GLuint) },
+            });
+        }
+    }
+    uniforms
+}
+
+fn blocks(program: GLuint) -> Vec<UniformBlock> {
+    let mut blocks = Vec::new();
+    unsafe {
+        let mut count = 0;
+        gl::GetProgramiv(program, gl::ACTIVE_UNIFORM_BLOCKS, &mut count);
+        let mut max_length = 0;
+        gl::GetProgramiv(program, gl::ACTIVE_UNIFORM_BLOCK_MAX_NAME_LENGTH, &mut max_length);
+
+        for index in 0..count as GLuint {
+            let mut buf = vec![0u8; max_length.max(1) as usize];
+            let mut length = 0;
+            gl::GetActiveUniformBlockName(program, index, max_length, &mut length,
+                                          buf.as_mut_ptr() as *mut GLchar);
+            let name = to_name(buf, length);
+
+            let mut binding = 0;
+            gl::GetActiveUniformBlockiv(program, index, gl::UNIFORM_BLOCK_BINDING, &mut binding);
+            let mut data_size = 0;
+            gl::GetActiveUniformBlockiv(program, index, gl::UNIFORM_BLOCK_DATA_SIZE, &mut data_size);
+            let mut member_count = 0;
+            gl::GetActiveUniformBlockiv(program, index, gl::UNIFORM_BLOCK_ACTIVE_UNIFORMS,
+                                        &mut member_count);
+            let mut indices = vec![0 as GLint; member_count as usize];
+            if member_count > 0 {
+                gl::GetActiveUniformBlockiv(program, index,
+                                            gl::UNIFORM_BLOCK_ACTIVE_UNIFORM_INDICES,
+                                            indices.as_mut_ptr());
+            }
+
+            let mut uniform_length = 0;
+            gl::GetProgramiv(program, gl::ACTIVE_UNIFORM_MAX_LENGTH, &mut uniform_length);
+            let uniforms = indices.iter().map(|member| {
+                let mut buf = vec![0u8; uniform_length.max(1) as usize];
+                let mut length = 0;
+                gl::GetActiveUniformName(program, *member as GLuint, uniform_length, &mut length,
+                                         buf.as_mut_ptr() as *mut GLchar);
+                to_name(buf, length)
+            }).collect();
+
+            blocks.push(UniformBlock { name, index, binding, data_size, uniforms });
+        }
+    }
+    blocks
+}
+
+fn to_name(mut buf: Vec<u8>, length: GLint) -> String {
+    buf.truncate(length.max(0) as usize);
+    String::from_utf8_lossy(&buf).into_owned()
+}
+
+/// Number of scalar components of a glsl type, `None` for samplers and other opaque types.
+pub fn components(gl_type: GLenum) -> Option<u32> {
+    let count = match gl_type {
+        gl::FLOAT | gl::INT | gl::UNSIGNED_INT | gl::BOOL | gl::DOUBLE => 1,
+        gl::FLOAT_VEC2 | gl::INT_VEC2 | gl::UNSIGNED_INT_VEC2 | gl::BOOL_VEC2 |
+        gl::DOUBLE_VEC2 => 2,
+        gl::FLOAT_VEC3 | gl::INT_VEC3 | gl::UNSIGNED_INT_VEC3 | gl::BOOL_VEC3 |
+        gl::DOUBLE_VEC3 => 3,
+        gl::FLOAT_VEC4 | gl::INT_VEC4 | gl::UNSIGNED_INT_VEC4 | gl::BOOL_VEC4 |
+        gl::DOUBLE_VEC4 | gl::FLOAT_MAT2 => 4,
+        gl::FLOAT_MAT3 => 9,
+        gl::FLOAT_MAT4 => 16,
+        _ => return None,
+    };
+    Some(count)
+}
+
+pub fn is_integer(gl_type: GLenum) -> bool {
+    matches!(gl_type,
+        gl::INT | gl::INT_VEC2 | gl::INT_VEC3 | gl::INT_VEC4 |
+        gl::UNSIGNED_INT | gl::UNSIGNED_INT_VEC2 | gl::UNSIGNED_INT_VEC3 | gl::UNSIGNED_INT_VEC4)
+}
+
+pub fn type_name(gl_type: GLenum) -> &'static str {
+    match gl_type {
+        gl::FLOAT => "float",
+        gl::FLOAT_VEC2 => "vec2",
+        gl::FLOAT_VEC3 => "vec3",
+        gl::FLOAT_VEC4 => "vec4",
+        gl::DOUBLE => "double",
+        gl::DOUBLE_VEC2 => "dvec2",
+        gl::DOUBLE_VEC3 => "dvec3",
+        gl::DOUBLE_VEC4 => "dvec4",
+        gl::INT => "int",
+        gl::INT_VEC2 => "ivec2",
+        gl::INT_VEC3 => "ivec3",
+        gl::INT_VEC4 => "ivec4",
+        gl::UNSIGNED_INT => "uint",
+        gl::UNSIGNED_INT_VEC2 => "uvec2",
+        gl::UNSIGNED_INT_VEC3 => "uvec3",
+        gl::UNSIGNED_INT_VEC4 => "uvec4",
+        gl::BOOL => "bool",
+        gl::BOOL_VEC2 => "bvec2",
+        gl::BOOL_VEC3 => "bvec3",
+        gl::BOOL_VEC4 => "bvec4",
+        gl::FLOAT_MAT2 => "mat2",
+        gl::FLOAT_MAT3 => "mat3",
+        gl::FLOAT_MAT4 => "mat4",
+        gl::SAMPLER_2D => "sampler2D",
+        gl::SAMPLER_3D => "sampler3D",
+        gl::SAMPLER_CUBE => "samplerCube",
+        gl::SAMPLER_2D_SHADOW => "sampler2DShadow",
+        gl::SAMPLER_2D_ARRAY => "sampler2DArray",
+        gl::SAMPLER_2D_MULTISAMPLE => "sampler2DMS",
+        gl::UNSIGNED_INT_SAMPLER_2D => "usampler2D",
+        gl::INT_SAMPLER_2D => "isampler2D",
+        _ => "unknown",
+    }
+}
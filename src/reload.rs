@@ -0,0 +1,149 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::error::Result;
+use crate::shader::Shader;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub enum ShaderSource {
+    Toml(PathBuf),
+    /// `name/name.vert` and `name/name.frag`, see `Shader::load`
+    Directory(PathBuf),
+}
+
+impl ShaderSource {
+    fn compile(&self) -> Result<Shader> {
+        match self {
+            ShaderSource::Toml(path) => Shader::from_toml(path),
+            ShaderSource::Directory(path) => Shader::load(path),
+        }
+    }
+
+    /// Every file the program is built from, including the shared `common/lib.glsl`.
+    fn files(&self) -> Vec<PathBuf> {
+        let (mut files, root) = match self {
+            ShaderSource::Toml(path) => (vec![path.clone()], path.parent()),
+            ShaderSource::Directory(path) => {
+                let name = path.file_name().and_then(|x| x.to_str()).unwrap_or_default();
+                (vec![path.join(format!("{}.vert", name)), path.join(format!("{}.frag", name))],
+                 path.parent())
+            }
+        };
+        if let Some(root) = root {
+            files.push(root.join("common").join("lib.glsl"));
+        }
+        files
+    }
+}
+
+/// A shader that recompiles itself when one of its source files is modified.
+/// The program is only swapped when compiling and linking succeed,
+/// otherwise the old program stays bound and the log is kept in `error`.
+pub struct ReloadableShader {
+    source: ShaderSource,
+    shader: Shader,
+    watched: Vec<(PathBuf, Option<SystemTime>)>,
+    last_poll: Instant,
+    pub error: Option<String>,
+}
+
+impl ReloadableShader {
+    pub fn from_toml(location: &Path) -> Result<ReloadableShader> {
+        ReloadableShader::new(ShaderSource::Toml(location.to_path_buf()))
+    }
+
+    pub fn load(location: &Path) -> Result<ReloadableShader> {
+        ReloadableShader::new(ShaderSource::Directory(location.to_path_buf()))
+    }
+
+    pub fn new(source: ShaderSource) -> Result<ReloadableShader> {
+        let shader = source.compile()?;
+        let watched = source.files()
+                            .into_iter()
+                            .map(|path| {
+                                let modified = modified(&path);
+                                (path, modified)
+                            })
+                            .collect();
+        Ok(ReloadableShader {
+            source,
+            shader,
+            watched,
+            last_poll: Instant::now(),
+            error: None,
+        })
+    }
+
+    pub fn shader(&self) -> &Shader {
+        &self.shader
+    }
+
+    /// Checks the watched files and recompiles if any of them changed.
+    /// Returns true if the program was swapped.
+    pub fn poll(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+
+        let mut changed = false;
+        for (path, last_modified) in self.watched.iter_mut() {
+            let current = modified(path);
+            if current != *last_modified {
+                *last_modified = current;
+                changed = true;
+            }
+        }
+        if changed {
+            self.reload()
+        } else {
+            false
+        }
+    }
+
+    pub fn reload(&mut self) -> bool {
+        match self.source.compile() {
+            Ok(shader) => {
+                self.shader = shader;
+                self.error = None;
+                true
+            }
+            Err(error) => {
+                self.error = Some(error.to_string());
+                false
+            }
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::HeadlessContext;
+
+    #[test]
+    fn directory_watches_both_stages() {
+        let files = ShaderSource::Directory(PathBuf::from("res/shader/flat")).files();
+        assert_eq!(files, vec![PathBuf::from("res/shader/flat/flat.vert"),
+                               PathBuf::from("res/shader/flat/flat.frag"),
+                               PathBuf::from("res/shader/common/lib.glsl")]);
+    }
+
+    #[test]
+    fn directory_shaders_load() {
+        let _context = HeadlessContext::new()
+            .unwrap_or_else(|e| panic!("needs a surfaceless EGL context: {}", e));
+        for name in ["flat", "present", "skybox"] {
+            let location = Path::new("res/shader").join(name);
+            let mut shader = ReloadableShader::load(&location)
+                .unwrap_or_else(|e| panic!("{}: {}", name, e));
+            assert!(shader.reload(), "{}: {:?}", name, shader.error);
+        }
+    }
+}
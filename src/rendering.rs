@@ -1,13 +1,20 @@
+use std::path::Path;
 use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
 
-use glam::Mat4;
+use glam::Vec2;
 use glfw::*;
-use imgui::{CollapsingHeader, Condition, Image, Slider, TextureId, WindowFlags};
+use imgui::{CollapsingHeader, Condition, Image, Slider, TextureId, TreeNode, Ui, WindowFlags};
 
-use crate::buffer::{RenderBuffer, RenderTarget};
-use crate::camera::Camera;
+use crate::assets::default_color_space;
+use crate::error::{Error, Result};
 use crate::glfw_bind::ImguiGLFW;
+use crate::headless::HeadlessContext;
+use crate::present::Tonemap;
+use crate::reflection::type_name;
+use crate::sampler::{max_anisotropy, Filter, Sampler, Wrap};
 use crate::scene::Scene;
+use crate::viewport::{Fit, Viewport};
 
 pub struct WindowContainer {
     pub(crate) window: Window,
@@ -17,24 +24,87 @@ pub struct WindowContainer {
     cursor_y: f64,
     pub(crate) delta_x: f64,
     pub(crate) delta_y: f64,
+    /// window size in screen coordinates, what the ui is laid out in
     pub(crate) width: u32,
     pub(crate) height: u32,
+    /// size in pixels, differs from the window size on high dpi displays
+    pub(crate) framebuffer_width: u32,
+    pub(crate) framebuffer_height: u32,
     delta_time: f32,
     last_frame: f32,
-    pub fps: u32,
 }
 
-pub struct RenderPath {
-    running: bool,
+impl WindowContainer {
+    /// Keeps the sizes and the cursor up to date.
+    fn handle_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::Key(Key::Escape, _, Action::Press, _) => {
+                self.window.set_should_close(true);
+            }
+            WindowEvent::Size(width, height) => {
+                // the ui follows right away, only the targets are debounced
+                self.width = (*width).max(1) as u32;
+                self.height = (*height).max(1) as u32;
+            }
+            // minimizing reports 0x0, keep the old targets until the window comes back
+            WindowEvent::FramebufferSize(width, height) if *width > 0 && *height > 0 => {
+                self.framebuffer_width = *width as u32;
+                self.framebuffer_height = *height as u32;
+                unsafe {
+                    gl::Viewport(0, 0, *width, *height);
+                }
+            }
+            WindowEvent::CursorPos(x, y) => {
+                self.delta_x = self.cursor_x - x;
+                self.delta_y = self.cursor_y - y;
+                self.cursor_x = *x;
+                self.cursor_y = *y;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A window with the ui drawn into it
+pub struct Frontend {
     pub ui: ImguiGLFW,
     pub ui_context: imgui::Context,
     pub window: WindowContainer,
+}
+
+/// What owns the gl context
+enum Surface {
+    Window(Box<Frontend>),
+    /// no window or display server, only the offscreen targets are rendered.
+    /// Only held so the context stays current until the render path is dropped.
+    Headless(#[allow(dead_code)] HeadlessContext),
+}
+
+/// Resizing recreates every render target, so while the window is dragged
+/// the new size is only applied once it stopped changing for this long.
+const RESIZE_DEBOUNCE: Duration = Duration::from_millis(150);
+
+// fields are dropped in order, so everything owning gl objects
+// has to come before the surface that owns the context
+pub struct RenderPath {
     scene: Option<Scene>,
+    running: bool,
+    error: Option<String>,
+    /// text field of the skybox settings
+    skybox_path: String,
+    /// where the scene image is shown, sizes the scene target
+    pub viewport: Viewport,
+    /// requested scene target size and when it was requested
+    pending_resize: Option<(u32, u32, Instant)>,
+    frame: u32,
+    frame_limit: Option<u32>,
+    pub fps: u32,
+    surface: Surface,
 }
 
 impl RenderPath {
-    pub fn new(width: u32, height: u32) -> RenderPath {
-        let mut glfw = init(FAIL_ON_ERRORS).unwrap();
+    pub fn new(width: u32, height: u32) -> Result<RenderPath> {
+        let mut glfw = init(LOG_ERRORS).map_err(|e| Error::Context(e.to_string()))?;
 
         glfw.window_hint(WindowHint::ContextVersion(4, 5));
         glfw.window_hint(WindowHint::OpenGlProfile(OpenGlProfileHint::Core));
@@ -44,7 +114,7 @@ impl RenderPath {
         let (mut window, events) = glfw.create_window(
             width, height,
             "Rust OpenGL", WindowMode::Windowed)
-                                       .expect("Failed to create GLFW window.");
+                                       .ok_or_else(|| Error::Context("no window with a 4.5 core profile".to_string()))?;
 
         window.set_all_polling(true);
         window.make_current();
@@ -60,7 +130,8 @@ impl RenderPath {
         let mut im_gui = imgui::Context::create();
         let im_gui_glfw = ImguiGLFW::new(&mut im_gui, &mut window);
 
-        let mut handle = WindowContainer {
+        let (framebuffer_width, framebuffer_height) = window.get_framebuffer_size();
+        let handle = WindowContainer {
             window,
             glfw,
             cursor_x: 0.0,
@@ -69,89 +140,174 @@ impl RenderPath {
             delta_y: 0.0,
             width,
             height,
+            framebuffer_width: framebuffer_width as u32,
+            framebuffer_height: framebuffer_height as u32,
             event_receiver: events,
             delta_time: 0.0,
             last_frame: 0.0,
-            fps: 0,
         };
 
-        let path = RenderPath {
-            running: true,
+        let frontend = Frontend {
             ui: im_gui_glfw,
             ui_context: im_gui,
             window: handle,
-            scene: None,
         };
+        Ok(RenderPath::create(Surface::Window(Box::new(frontend)),
+                              framebuffer_width as u32, framebuffer_height as u32, None))
+    }
 
-        path
+    /// Renders with a surfaceless EGL context and stops after `frames` frames.
+    /// Needs neither a window nor a display server, Mesa's llvmpipe is enough.
+    pub fn headless(width: u32, height: u32, frames: u32) -> Result<RenderPath> {
+        let context = HeadlessContext::new()?;
+        Ok(RenderPath::create(Surface::Headless(context), width, height, Some(frames)))
     }
-    pub fn start(&mut self) {
-        {
-            let mut target = RenderTarget::new(self.window.width, self.window.height);
-            target.new_texture(gl::COLOR_ATTACHMENT0);
-            target.new_buffer(gl::DEPTH_STENCIL_ATTACHMENT);
 
-            let scene = Scene::new(&self.window);
-            self.scene = Some(scene);
+    fn create(surface: Surface, width: u32, height: u32, frame_limit: Option<u32>) -> RenderPath {
+        RenderPath {
+            running: true,
+            scene: None,
+            error: None,
+            skybox_path: String::new(),
+            viewport: Viewport::new(width, height),
+            pending_resize: None,
+            frame: 0,
+            frame_limit,
+            fps: 0,
+            surface,
         }
     }
 
-    pub fn projection(&mut self, camera: &Camera) -> Mat4 {
-        Mat4::perspective_rh_gl(
-            camera.fov.to_radians(),
-            self.window.width as f32 / self.window.height as f32,
-            camera.near_plane,
-            camera.far_plane)
+    pub fn start(&mut self, model: &Path) {
+        let size = self.viewport.target_size();
+        match Scene::new(size.x, size.y, model) {
+            Ok(scene) => self.scene = Some(scene),
+            Err(error) => self.error = Some(error.to_string()),
+        }
     }
 
-    pub fn render(&mut self) {
-        unsafe {
-            gl::ClearColor(0.3, 0.3, 0.5, 1.0);
-            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+    /// `None` when rendering headless
+    pub fn window(&self) -> Option<&WindowContainer> {
+        match &self.surface {
+            Surface::Window(frontend) => Some(&frontend.window),
+            Surface::Headless(_) => None,
         }
+    }
 
-        let mut scene = self.scene.take().unwrap();
-        scene.render(self);
-
-        self.scene = Some(scene);
+    /// Hands the window events to the render path and the ui.
+    pub fn poll_events(&mut self) {
+        let events = match &mut self.surface {
+            Surface::Window(frontend) => {
+                frontend.window.glfw.poll_events();
+                glfw::flush_messages(&frontend.window.event_receiver).map(|x| x.1).collect::<Vec<_>>()
+            }
+            Surface::Headless(_) => return,
+        };
+        for event in events.iter() {
+            self.handle_event(event);
+            if let Surface::Window(frontend) = &mut self.surface {
+                frontend.ui.handle_event(&mut frontend.ui_context, event);
+            }
+        }
+    }
 
-        // let mut s = self.scene.unwrap();
-        // s.render(self);
+    pub fn render(&mut self) {
+        self.apply_resize();
+        // there is no default framebuffer without a window
+        if self.window().is_some() {
+            unsafe {
+                gl::ClearColor(0.3, 0.3, 0.5, 1.0);
+                gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+            }
+        }
 
-        self.ui();
+        if let Some(scene) = self.scene.as_mut() {
+            let window = match &self.surface {
+                Surface::Window(frontend) => Some(&frontend.window),
+                Surface::Headless(_) => None,
+            };
+            scene.render(window);
+        }
 
-        self.window.window.render_context().swap_buffers();
-        // self.window.glfw.set_swap_interval(SwapInterval::Sync(1));
+        if self.window().is_some() {
+            self.ui();
+        } else {
+            // nothing is swapped, wait for the frame so the commands don't pile up
+            unsafe {
+                gl::Finish();
+            }
+        }
 
-        self.window.delta_x = 0.0;
-        self.window.delta_y = 0.0;
+        if let Surface::Window(frontend) = &mut self.surface {
+            let window = &mut frontend.window;
+            window.window.render_context().swap_buffers();
+            // window.glfw.set_swap_interval(SwapInterval::Sync(1));
+            window.delta_x = 0.0;
+            window.delta_y = 0.0;
+        }
+        self.frame += 1;
     }
 
 
     pub fn handle_event(&mut self, event: &WindowEvent) {
-        if self.window.window.should_close() {
+        let window = match &mut self.surface {
+            Surface::Window(frontend) => &mut frontend.window,
+            Surface::Headless(_) => return,
+        };
+        window.handle_event(event);
+        if window.window.should_close() {
             self.running = false;
         }
         match event {
-            WindowEvent::Key(Key::Escape, _, Action::Press, _) => {
-                self.window.window.set_should_close(true);
+            WindowEvent::Key(Key::F12, _, Action::Press, _) => {
+                self.screenshot(Path::new("screenshot.png"));
             }
-            WindowEvent::CursorPos(x, y) => {
-                let delta_x = self.window.cursor_x - x;
-                let delta_y = self.window.cursor_y - y;
+            WindowEvent::MouseButton(MouseButton::Button1, Action::Press, _) => {
+                if let (Some((x, y)), Some(scene)) = (self.hovered_pixel(), self.scene.as_mut()) {
+                    scene.selected = scene.pick(x, y);
+                }
+            }
+            _ => {}
+        }
+    }
 
-                self.window.delta_x = delta_x;
-                self.window.delta_y = delta_y;
+    /// Asks for a new scene target size, applied once it stopped changing.
+    fn request_resize(&mut self, width: u32, height: u32) {
+        let current = self.scene.as_ref().map(|x| (x.target.width(), x.target.height()));
+        match self.pending_resize {
+            Some((w, h, _)) if (w, h) == (width, height) => {}
+            _ if current == Some((width, height)) => self.pending_resize = None,
+            _ => self.pending_resize = Some((width, height, Instant::now())),
+        }
+    }
 
-                self.window.cursor_x = *x;
-                self.window.cursor_y = *y;
+    fn apply_resize(&mut self) {
+        let (width, height) = match self.pending_resize {
+            Some((width, height, at)) if at.elapsed() >= RESIZE_DEBOUNCE => (width, height),
+            _ => return,
+        };
+        self.pending_resize = None;
+        if let Some(scene) = self.scene.as_mut() {
+            match scene.resize(width, height) {
+                Ok(_) => self.viewport.set_target_size(width, height),
+                Err(error) => self.error = Some(error.to_string()),
             }
-            _ => {}
         }
     }
 
+    /// The scene pixel under the mouse, origin at the bottom left
+    pub fn hovered_pixel(&self) -> Option<(u32, u32)> {
+        let window = self.window()?;
+        let cursor = Vec2::new(window.cursor_x as f32, window.cursor_y as f32);
+        self.viewport.to_pixel(cursor).map(|x| (x.x, x.y))
+    }
+
     pub fn ui(&mut self) {
-        let style = self.ui_context.style_mut();
+        let Frontend { ui: imgui_glfw, ui_context, window } = match &mut self.surface {
+            Surface::Window(frontend) => &mut **frontend,
+            Surface::Headless(_) => return,
+        };
+        let style = ui_context.style_mut();
         style.frame_border_size = 0.0;
         style.child_border_size = 0.0;
         style.popup_border_size = 0.0;
@@ -160,32 +316,41 @@ impl RenderPath {
         style.frame_padding = [0.0; 2];
         style.window_padding = [0.0; 2];
 
-        let ui = self.ui.frame(&mut self.window.window, &mut self.ui_context);
+        let ui = imgui_glfw.frame(&mut window.window, ui_context);
 
-        let aspect = self.window.width as f32 / self.window.height as f32;
-        let reduced_height = self.window.height as f32 - (1.0 / aspect) * 200.0;
+        let aspect = window.width as f32 / window.height as f32;
+        let reduced_height = (window.height as f32 - (1.0 / aspect) * 200.0).max(0.0);
 
+        let canvas_width = window.width.saturating_sub(200) as f32;
         let canvas = imgui::Window::new("Buffer")
-            .position([200.0, 0.0], Condition::Appearing)
-            .size([(self.window.width - 200) as f32, reduced_height],
-                  Condition::Appearing);
+            .position([200.0, 0.0], Condition::Always)
+            .size([canvas_width, reduced_height], Condition::Always);
 
         let canvas = canvas.flags(WindowFlags::NO_MOVE | WindowFlags::NO_RESIZE |
             WindowFlags::NO_COLLAPSE | WindowFlags::NO_SCROLLBAR | WindowFlags::NO_TITLE_BAR);
 
-        let texture =
-            self.scene.as_mut().unwrap().target.get(gl::COLOR_ATTACHMENT0)
-                .unwrap().as_texture()
-                .unwrap();
-
-        canvas.build(&ui, || {
-            let img = Image::new(
-                TextureId::new(texture.id as usize),
-                [(self.window.width - 200) as f32, reduced_height]);
-            let img = img.uv0([0.0, 1.0]);
-            let img = img.uv1([1.0, 0.0]);
-            img.build(&ui);
-        });
+        let texture = self.scene.as_mut()
+                          .and_then(|scene| scene.presenter.target.get(gl::COLOR_ATTACHMENT0))
+                          .and_then(|attachment| attachment.as_texture())
+                          .map(|texture| texture.id());
+
+        let scale = Vec2::new(window.framebuffer_width as f32 / window.width as f32,
+                              window.framebuffer_height as f32 / window.height as f32);
+        let mut requested = None;
+        if let Some(texture) = texture {
+            canvas.build(&ui, || {
+                let region_position = ui.cursor_screen_pos();
+                let size = self.viewport.layout(region_position, ui.content_region_avail(), scale);
+                requested = Some((size.x, size.y));
+                ui.set_cursor_screen_pos(self.viewport.position());
+                let img = Image::new(
+                    TextureId::new(texture as usize),
+                    self.viewport.size());
+                let img = img.uv0([0.0, 1.0]);
+                let img = img.uv1([1.0, 0.0]);
+                img.build(&ui);
+            });
+        }
 
         let w = imgui::Window::new("Settings")
             .position([0.0, 0.0], Condition::Appearing)
@@ -195,39 +360,226 @@ impl RenderPath {
             ;
 
         w.build(&ui, || {
-            let mut x = self.scene.as_mut().unwrap().debug_camera.position.to_array();
-            ui.input_float3("input float3", &mut x)
-              .build();
-            ui.spacing();
-            if CollapsingHeader::new("Camera").build(&ui) {
-                Slider::new("FOV", 10f32, 90f32).build(&ui, &mut self.scene.as_mut().unwrap()
-                                                                     .debug_camera.fov);
-                Slider::new("Speed", 0.1f32, 60f32).build(&ui, &mut self.scene.as_mut().unwrap()
-                                                                        .debug_camera.speed);
+            if let Some(error) = &self.error {
+                ui.text_wrapped(error);
+                ui.spacing();
+            }
+            if let Some(scene) = self.scene.as_mut() {
+                if let Some(error) = scene.shader_error() {
+                    ui.text_wrapped(error);
+                    ui.spacing();
+                }
+                let mut x = scene.debug_camera.position.to_array();
+                ui.input_float3("input float3", &mut x)
+                  .build();
+                ui.spacing();
+                if CollapsingHeader::new("Camera").build(&ui) {
+                    Slider::new("FOV", 10f32, 90f32).build(&ui, &mut scene.debug_camera.fov);
+                    Slider::new("Speed", 0.1f32, 60f32).build(&ui, &mut scene.debug_camera.speed);
+                    ui.checkbox("Collide", &mut scene.camera_collision);
+                    if ui.button("Frame scene") {
+                        if let Some(sphere) = scene.bounding_sphere() {
+                            scene.debug_camera.frame(&sphere);
+                        }
+                    }
+                    let bounds = scene.bounds();
+                    if !bounds.is_empty() {
+                        let size = bounds.size();
+                        ui.text(format!("size {:.1} {:.1} {:.1}", size.x, size.y, size.z));
+                    }
+                }
+                if CollapsingHeader::new("Viewport").build(&ui) {
+                    let fit = &mut self.viewport.fit;
+                    ui.radio_button("Fill", fit, Fit::Fill);
+                    ui.radio_button("16:9", fit, Fit::Letterbox(16.0 / 9.0));
+                    ui.radio_button("4:3", fit, Fit::Letterbox(4.0 / 3.0));
+                    ui.radio_button("1:1", fit, Fit::Letterbox(1.0));
+                    let cursor = Vec2::new(window.cursor_x as f32, window.cursor_y as f32);
+                    if let Some(pixel) = self.viewport.to_pixel(cursor) {
+                        ui.text(format!("pixel {} {}", pixel.x, pixel.y));
+                    }
+                    if let Some(ray) = self.viewport.ray(&scene.debug_camera, cursor) {
+                        let direction = ray.direction;
+                        ui.text(format!("ray {:.2} {:.2} {:.2}", direction.x, direction.y, direction.z));
+                        if let Some((object, hit)) = scene.raycast(&ray) {
+                            ui.text(format!("hit object {} mesh {}", object, hit.mesh));
+                            ui.text(format!("triangle {} at {:.1}", hit.triangle, hit.t));
+                            let weights = hit.barycentric;
+                            ui.text(format!("weights {:.2} {:.2} {:.2}", weights.x, weights.y, weights.z));
+                            ui.text(format!("{} surfaces along the ray", scene.raycast_all(&ray).len()));
+                        }
+                    }
+                }
+                if CollapsingHeader::new("Selection").build(&ui) {
+                    let mut picking = scene.picking();
+                    if ui.checkbox("Picking", &mut picking) {
+                        if let Err(error) = scene.set_picking(picking) {
+                            self.error = Some(error.to_string());
+                        }
+                    }
+                    let selected = scene.selected.and_then(|pick| {
+                        let model = &scene.objects.get(pick.object)?.model;
+                        Some((pick, model.meshes.get(pick.mesh)?, model))
+                    });
+                    match selected {
+                        Some((pick, mesh, model)) => {
+                            let material = mesh.material
+                                               .and_then(|x| model.materials.get(x))
+                                               .map_or("none", |x| x.name.as_str());
+                            ui.text(format!("object {} mesh {}", pick.object, pick.mesh));
+                            ui.text_wrapped(format!("name {}", mesh.name));
+                            ui.text_wrapped(format!("material {}", material));
+                            ui.text(format!("{} vertices", mesh.vertex_count()));
+                            ui.text(format!("{} triangles", mesh.triangle_count()));
+                            let size = mesh.bounds().size();
+                            ui.text(format!("size {:.1} {:.1} {:.1}", size.x, size.y, size.z));
+                            ui.text(format!("radius {:.1}", mesh.bounding_sphere().radius));
+                        }
+                        None => ui.text("click a mesh to select it"),
+                    }
+                }
+                if CollapsingHeader::new("Output").build(&ui) {
+                    let presenter = &mut scene.presenter;
+                    Slider::new("Exposure", 0.05f32, 8f32).build(&ui, &mut presenter.exposure);
+                    ui.radio_button("Clamp", &mut presenter.tonemap, Tonemap::Clamp);
+                    ui.radio_button("Reinhard", &mut presenter.tonemap, Tonemap::Reinhard);
+                    ui.radio_button("ACES", &mut presenter.tonemap, Tonemap::Aces);
+                }
+                if CollapsingHeader::new("Skybox").build(&ui) {
+                    ui.input_text("Path", &mut self.skybox_path).build();
+                    if ui.button("Load") {
+                        let path = Path::new(self.skybox_path.trim());
+                        match scene.load_skybox(path, default_color_space(path)) {
+                            Ok(_) => self.error = None,
+                            Err(error) => self.error = Some(error.to_string()),
+                        }
+                    }
+                    if let Some(skybox) = scene.skybox.as_mut() {
+                        ui.same_line();
+                        if ui.button("Remove") {
+                            scene.skybox = None;
+                        } else {
+                            ui.text(format!("Loaded: {}", skybox.path.display()));
+                            ui.checkbox("Show", &mut skybox.enabled);
+                            Slider::new("Intensity", 0f32, 4f32).build(&ui, &mut skybox.intensity);
+                        }
+                    }
+                }
+                if CollapsingHeader::new("Materials").build(&ui) {
+                    ui.text(format!("{} textures", scene.assets.textures.alive()));
+                    for path in scene.assets.textures.missing() {
+                        ui.text_wrapped(format!("missing {}", path.display()));
+                    }
+                    for (i, object) in scene.objects.iter().enumerate() {
+                        let _object_id = ui.push_id(i as i32);
+                        for material in object.model.materials.iter() {
+                            if let Some(sampler) = &material.sampler {
+                                TreeNode::new(material.name.as_str()).build(&ui, || {
+                                    sampler_ui(&ui, sampler);
+                                });
+                            }
+                        }
+                    }
+                }
+                scene.debug_camera.position.x = x[0];
+                scene.debug_camera.position.y = x[1];
+                scene.debug_camera.position.z = x[2];
+                if CollapsingHeader::new("Shader").build(&ui) {
+                    let reflection = scene.shader().reflect();
+                    let array = |size: i32| if size > 1 { format!("[{}]", size) } else { String::new() };
+                    for attribute in reflection.attributes.iter() {
+                        ui.text(format!("in {} {}{} ({})", type_name(attribute.gl_type), attribute.name,
+                                        array(attribute.size), attribute.location));
+                    }
+                    for uniform in reflection.uniforms.iter().filter(|x| x.block.is_none()) {
+                        ui.text(format!("uniform {} {}{}", type_name(uniform.gl_type), uniform.name,
+                                        array(uniform.size)));
+                    }
+                    for block in reflection.blocks.iter() {
+                        ui.text(format!("block {} {} at binding {} ({} bytes)", block.index, block.name,
+                                        block.binding, block.data_size));
+                        for name in block.uniforms.iter() {
+                            ui.text(format!("  {}", name));
+                        }
+                    }
+                    for name in scene.shader().missing_uniforms() {
+                        ui.text_wrapped(format!("uniform {} is not active", name));
+                    }
+                }
             }
-            self.scene.as_mut().unwrap().debug_camera.position.x = x[0];
-            self.scene.as_mut().unwrap().debug_camera.position.y = x[1];
-            self.scene.as_mut().unwrap().debug_camera.position.z = x[2];
             ui.spacing();
-            let current_frame = self.window.glfw.get_time() as f32;
-            self.window.delta_time = current_frame - self.window.last_frame;
-            self.window.last_frame = current_frame;
+            let current_frame = window.glfw.get_time() as f32;
+            window.delta_time = current_frame - window.last_frame;
+            window.last_frame = current_frame;
 
-            ui.text(format!("{:.2}ms", self.window.delta_time * 1000.0));
-            ui.text(format!("{:.0} fps", self.window.fps));
+            ui.text(format!("{:.2}ms", window.delta_time * 1000.0));
+            ui.text(format!("{:.0} fps", self.fps));
         });
 
-        self.ui.draw(ui, &mut self.window.window);
+        imgui_glfw.draw(ui, &mut window.window);
+
+        if let Some((width, height)) = requested {
+            self.request_resize(width, height);
+        }
+    }
+
+    pub fn screenshot(&mut self, path: &Path) {
+        if let Some(scene) = &self.scene {
+            if let Err(error) = scene.presenter.target.save_png(gl::COLOR_ATTACHMENT0, path) {
+                self.error = Some(error.to_string());
+            }
+        }
+    }
+
+    /// The last error, shown in the ui
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
     }
 
     pub fn running(&self) -> bool {
-        self.running
+        match self.frame_limit {
+            Some(limit) => self.running && self.frame < limit,
+            None => self.running
+        }
     }
 
-    pub fn dispose(&mut self) {}
+    pub fn dispose(&mut self) {
+        self.scene = None;
+    }
+}
+
+fn sampler_ui(ui: &Ui, sampler: &Sampler) {
+    let mut desc = sampler.desc();
+    ui.text("Filter");
+    let mut mip_filter = desc.mip_filter;
+    ui.radio_button("Nearest", &mut mip_filter, None);
+    ui.radio_button("Bilinear", &mut mip_filter, Some(Filter::Nearest));
+    ui.radio_button("Trilinear", &mut mip_filter, Some(Filter::Linear));
+    if mip_filter != desc.mip_filter {
+        let filter = if mip_filter.is_none() { Filter::Nearest } else { Filter::Linear };
+        desc.mip_filter = mip_filter;
+        desc.min_filter = filter;
+        desc.mag_filter = filter;
+    }
+    ui.text("Wrap");
+    let mut wrap = desc.wrap_s;
+    ui.radio_button("Repeat", &mut wrap, Wrap::Repeat);
+    ui.radio_button("Mirrored", &mut wrap, Wrap::MirroredRepeat);
+    ui.radio_button("Clamp", &mut wrap, Wrap::ClampToEdge);
+    ui.radio_button("Border", &mut wrap, Wrap::ClampToBorder);
+    if wrap != desc.wrap_s {
+        desc = desc.wrap(wrap);
+    }
+    if wrap == Wrap::ClampToBorder {
+        imgui::ColorEdit::new("Border color", &mut desc.border_color).build(ui);
+    }
+    Slider::new("Anisotropy", 1f32, max_anisotropy()).build(ui, &mut desc.anisotropy);
+    Slider::new("LOD bias", -4f32, 4f32).build(ui, &mut desc.lod_bias);
+    sampler.set(desc);
 }
 
 pub mod shader_mode {
+    #[allow(dead_code)]
     pub fn line() {
         unsafe {
             gl::PolygonMode(gl::FRONT_AND_BACK, gl::LINE);
@@ -240,9 +592,30 @@ pub mod shader_mode {
         }
     }
 
+    #[allow(dead_code)]
     pub fn point() {
         unsafe {
             gl::PolygonMode(gl::FRONT_AND_BACK, gl::POINT);
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headless_renders_the_frame_limit() {
+        let mut path = RenderPath::headless(64, 48, 3)
+            .unwrap_or_else(|e| panic!("needs a surfaceless EGL context: {}", e));
+        path.start(Path::new("res/model/teapot.obj"));
+        assert_eq!(path.error(), None);
+        while path.running() {
+            path.render();
+        }
+        assert_eq!(path.frame, 3);
+        let scene = path.scene.as_ref().unwrap();
+        assert_eq!((scene.target.width(), scene.target.height()), (64, 48));
+        assert_eq!(unsafe { gl::GetError() }, gl::NO_ERROR);
+    }
+}
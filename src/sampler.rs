@@ -0,0 +1,287 @@
+use std::cell::Cell;
+use std::ffi::{c_char, CStr};
+use std::sync::OnceLock;
+
+use gl::types::GLenum;
+
+// core in 4.6 and EXT_texture_filter_anisotropic before, same values, not in the 4.5 bindings
+const TEXTURE_MAX_ANISOTROPY: GLenum = 0x84FE;
+const MAX_TEXTURE_MAX_ANISOTROPY: GLenum = 0x84FF;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Wrap {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+    ClampToBorder,
+}
+
+impl Wrap {
+    pub fn gl_enum(&self) -> GLenum {
+        match self {
+            Wrap::Repeat => gl::REPEAT,
+            Wrap::MirroredRepeat => gl::MIRRORED_REPEAT,
+            Wrap::ClampToEdge => gl::CLAMP_TO_EDGE,
+            Wrap::ClampToBorder => gl::CLAMP_TO_BORDER,
+        }
+    }
+}
+
+/// Depth comparison for shadow samplers
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Compare {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl Compare {
+    pub fn gl_enum(&self) -> GLenum {
+        match self {
+            Compare::Less => gl::LESS,
+            Compare::LessEqual => gl::LEQUAL,
+            Compare::Greater => gl::GREATER,
+            Compare::GreaterEqual => gl::GEQUAL,
+        }
+    }
+}
+
+/// Everything about how a texture is sampled, independent of the texture itself.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SamplerDesc {
+    pub min_filter: Filter,
+    pub mag_filter: Filter,
+    /// `None` samples level 0 only
+    pub mip_filter: Option<Filter>,
+    pub wrap_s: Wrap,
+    pub wrap_t: Wrap,
+    pub wrap_r: Wrap,
+    /// only used with `Wrap::ClampToBorder`
+    pub border_color: [f32; 4],
+    /// 1 disables anisotropic filtering, clamped to what the driver supports
+    pub anisotropy: f32,
+    pub lod_bias: f32,
+    /// compares against the reference in the texture coordinates, only for depth textures
+    pub compare: Option<Compare>,
+}
+
+impl Default for SamplerDesc {
+    /// Trilinear and repeating
+    fn default() -> SamplerDesc {
+        SamplerDesc {
+            min_filter: Filter::Linear,
+            mag_filter: Filter::Linear,
+            mip_filter: Some(Filter::Linear),
+            wrap_s: Wrap::Repeat,
+            wrap_t: Wrap::Repeat,
+            wrap_r: Wrap::Repeat,
+            border_color: [0.0; 4],
+            anisotropy: 1.0,
+            lod_bias: 0.0,
+            compare: None,
+        }
+    }
+}
+
+impl SamplerDesc {
+    /// Trilinear with the highest anisotropy the driver allows, for material textures
+    pub fn anisotropic() -> SamplerDesc {
+        SamplerDesc {
+            anisotropy: max_anisotropy(),
+            ..SamplerDesc::default()
+        }
+    }
+
+    /// Hardware filtered depth comparison for `sampler2DShadow`, outside of the map is lit
+    #[allow(dead_code)]
+    pub fn shadow() -> SamplerDesc {
+        SamplerDesc {
+            mip_filter: None,
+            border_color: [1.0; 4],
+            compare: Some(Compare::LessEqual),
+            ..SamplerDesc::default().wrap(Wrap::ClampToBorder)
+        }
+    }
+
+    pub fn wrap(self, wrap: Wrap) -> SamplerDesc {
+        SamplerDesc {
+            wrap_s: wrap,
+            wrap_t: wrap,
+            wrap_r: wrap,
+            ..self
+        }
+    }
+
+    pub fn min_filter_enum(&self) -> GLenum {
+        match (self.min_filter, self.mip_filter) {
+            (Filter::Nearest, None) => gl::NEAREST,
+            (Filter::Linear, None) => gl::LINEAR,
+            (Filter::Nearest, Some(Filter::Nearest)) => gl::NEAREST_MIPMAP_NEAREST,
+            (Filter::Nearest, Some(Filter::Linear)) => gl::NEAREST_MIPMAP_LINEAR,
+            (Filter::Linear, Some(Filter::Nearest)) => gl::LINEAR_MIPMAP_NEAREST,
+            (Filter::Linear, Some(Filter::Linear)) => gl::LINEAR_MIPMAP_LINEAR,
+        }
+    }
+
+    pub fn mag_filter_enum(&self) -> GLenum {
+        match self.mag_filter {
+            Filter::Nearest => gl::NEAREST,
+            Filter::Linear => gl::LINEAR,
+        }
+    }
+}
+
+static MAX_ANISOTROPY: OnceLock<f32> = OnceLock::new();
+
+/// Highest anisotropy level the driver supports, 1 if it has no anisotropic filtering.
+/// Queried once, the first call has to have a current context.
+pub fn max_anisotropy() -> f32 {
+    *MAX_ANISOTROPY.get_or_init(|| {
+        if !has_anisotropic_filtering() {
+            return 1.0;
+        }
+        let mut max = 1.0;
+        unsafe {
+            gl::GetFloatv(MAX_TEXTURE_MAX_ANISOTROPY, &mut max);
+        }
+        max.max(1.0)
+    })
+}
+
+/// Asking for the maximum without support is an `INVALID_ENUM`, so check first
+/// instead of clearing errors that might not be ours.
+fn has_anisotropic_filtering() -> bool {
+    let (mut major, mut minor, mut count) = (0, 0, 0);
+    unsafe {
+        gl::GetIntegerv(gl::MAJOR_VERSION, &mut major);
+        gl::GetIntegerv(gl::MINOR_VERSION, &mut minor);
+        gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut count);
+    }
+    if (major, minor) >= (4, 6) {
+        return true;
+    }
+    (0..count.max(0) as u32).any(|i| {
+        let name = unsafe { gl::GetStringi(gl::EXTENSIONS, i) };
+        if name.is_null() {
+            return false;
+        }
+        let name = unsafe { CStr::from_ptr(name as *const c_char) }.to_bytes();
+        name == b"GL_EXT_texture_filter_anisotropic" || name == b"GL_ARB_texture_filter_anisotropic"
+    })
+}
+
+/// A gl sampler object, which overrides the sampling state of any texture bound to the same unit.
+/// The description can be changed through a shared reference, so materials inside
+/// an `Rc<Model>` stay editable.
+pub struct Sampler {
+    id: u32,
+    desc: Cell<SamplerDesc>,
+}
+
+impl Sampler {
+    pub fn new(desc: SamplerDesc) -> Sampler {
+        let mut id = 0;
+        unsafe {
+            gl::GenSamplers(1, &mut id);
+        }
+        let sampler = Sampler {
+            id,
+            desc: Cell::new(desc),
+        };
+        sampler.apply(&desc);
+        sampler
+    }
+
+    pub fn desc(&self) -> SamplerDesc {
+        self.desc.get()
+    }
+
+    pub fn set(&self, desc: SamplerDesc) {
+        if desc != self.desc.get() {
+            self.desc.set(desc);
+            self.apply(&desc);
+        }
+    }
+
+    fn apply(&self, desc: &SamplerDesc) {
+        unsafe {
+            gl::SamplerParameteri(self.id, gl::TEXTURE_MIN_FILTER, desc.min_filter_enum() as i32);
+            gl::SamplerParameteri(self.id, gl::TEXTURE_MAG_FILTER, desc.mag_filter_enum() as i32);
+            gl::SamplerParameteri(self.id, gl::TEXTURE_WRAP_S, desc.wrap_s.gl_enum() as i32);
+            gl::SamplerParameteri(self.id, gl::TEXTURE_WRAP_T, desc.wrap_t.gl_enum() as i32);
+            gl::SamplerParameteri(self.id, gl::TEXTURE_WRAP_R, desc.wrap_r.gl_enum() as i32);
+            gl::SamplerParameterfv(self.id, gl::TEXTURE_BORDER_COLOR, desc.border_color.as_ptr());
+            gl::SamplerParameterf(self.id, gl::TEXTURE_LOD_BIAS, desc.lod_bias);
+            match desc.compare {
+                Some(compare) => {
+                    gl::SamplerParameteri(self.id, gl::TEXTURE_COMPARE_MODE,
+                                          gl::COMPARE_REF_TO_TEXTURE as i32);
+                    gl::SamplerParameteri(self.id, gl::TEXTURE_COMPARE_FUNC, compare.gl_enum() as i32);
+                }
+                None => gl::SamplerParameteri(self.id, gl::TEXTURE_COMPARE_MODE, gl::NONE as i32),
+            }
+            let max = max_anisotropy();
+            if max > 1.0 {
+                gl::SamplerParameterf(self.id, TEXTURE_MAX_ANISOTROPY, desc.anisotropy.clamp(1.0, max));
+            }
+        }
+    }
+
+    pub fn bind(&self, unit: u32) {
+        unsafe {
+            gl::BindSampler(unit, self.id);
+        }
+    }
+
+    pub fn unbind(unit: u32) {
+        unsafe {
+            gl::BindSampler(unit, 0);
+        }
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteSamplers(1, &self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::HeadlessContext;
+
+    fn parameter(sampler: &Sampler, name: GLenum) -> i32 {
+        let mut value = 0;
+        unsafe {
+            gl::GetSamplerParameteriv(sampler.id, name, &mut value);
+        }
+        value
+    }
+
+    #[test]
+    fn compare_state_is_applied() {
+        let _context = HeadlessContext::new()
+            .unwrap_or_else(|e| panic!("needs a surfaceless EGL context: {}", e));
+        let sampler = Sampler::new(SamplerDesc::shadow());
+        assert_eq!(parameter(&sampler, gl::TEXTURE_COMPARE_MODE), gl::COMPARE_REF_TO_TEXTURE as i32);
+        assert_eq!(parameter(&sampler, gl::TEXTURE_COMPARE_FUNC), gl::LEQUAL as i32);
+        assert_eq!(parameter(&sampler, gl::TEXTURE_WRAP_S), gl::CLAMP_TO_BORDER as i32);
+
+        sampler.set(SamplerDesc { compare: Some(Compare::Greater), ..sampler.desc() });
+        assert_eq!(parameter(&sampler, gl::TEXTURE_COMPARE_FUNC), gl::GREATER as i32);
+        sampler.set(SamplerDesc { compare: None, ..sampler.desc() });
+        assert_eq!(parameter(&sampler, gl::TEXTURE_COMPARE_MODE), gl::NONE as i32);
+        assert_eq!(unsafe { gl::GetError() }, gl::NO_ERROR);
+    }
+}
@@ -1,78 +1,165 @@
 use std::path::Path;
+use std::rc::Rc;
 
-use glam::{EulerRot, Mat4, Quat, Vec3};
+use gl::types::GLenum;
+use glam::{Mat4, Vec3};
 use glfw::{Action, Key, MouseButton};
 
 use crate::buffer::RenderTarget;
+use crate::alignment::VertexFormat;
 use crate::camera::Camera;
-use crate::model::{Model, TexVertex, Vertex};
+use crate::assets::AssetManager;
+use crate::error::Result;
+use crate::geometry::{Aabb, BoundingSphere, Ray};
+use crate::material::srgb_to_linear;
+use crate::model::{LitVertex, Model};
 use crate::rendering::{shader_mode, WindowContainer};
-use crate::RenderPath;
+use crate::present::Presenter;
+use crate::raycast::Hit;
+use crate::reload::ReloadableShader;
 use crate::shader::Shader;
+use crate::skybox::Skybox;
+use crate::textures::{ColorSpace, TextureFormat};
+
+/// Written by the lit shader, see `Pick`
+pub const OBJECT_ID_ATTACHMENT: GLenum = gl::COLOR_ATTACHMENT1;
+
+/// A mesh of one of the scene objects.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Pick {
+    pub object: usize,
+    pub mesh: usize,
+}
+
+impl Pick {
+    /// Object in the upper and mesh in the lower 16 bits, 0 is left for the background.
+    /// `None` when either doesn't fit, such a mesh is drawn as background and can't be picked.
+    pub fn id(&self) -> Option<u32> {
+        let object = u32::try_from(self.object).ok().filter(|x| *x <= 0xffff)?;
+        let mesh = u32::try_from(self.mesh).ok().filter(|x| *x <= 0xffff)?;
+        (object << 16 | mesh).checked_add(1)
+    }
+
+    pub fn from_id(id: u32) -> Option<Pick> {
+        let id = id.checked_sub(1)?;
+        Some(Pick {
+            object: (id >> 16) as usize,
+            mesh: (id & 0xffff) as usize,
+        })
+    }
+}
+
+/// How close the debug camera gets to geometry when collision is on, as a fraction of the
+/// scene radius like the speed set by `Camera::frame`
+const CAMERA_RADIUS: f32 = 0.005;
+
+pub struct SceneObject {
+    pub model: Rc<Model<LitVertex>>,
+    pub transform: Mat4,
+}
+
+impl SceneObject {
+    fn local_ray(&self, ray: &Ray) -> Ray {
+        let inverse = self.transform.inverse();
+        Ray::new(inverse.transform_point3(ray.origin), inverse.transform_vector3(ray.direction))
+    }
+
+    /// Moves a model space hit of `ray` to world space
+    fn world_hit(&self, ray: &Ray, mut hit: Hit) -> Hit {
+        hit.point = self.transform.transform_point3(hit.point);
+        hit.t = (hit.point - ray.origin).length();
+        hit
+    }
+}
 
 pub struct Scene {
+    /// linear hdr color, see `presenter.target` for the displayable image
     pub target: RenderTarget,
-    model: Model<TexVertex>,
-    shader: Shader,
+    pub presenter: Presenter,
+    pub assets: AssetManager,
+    pub objects: Vec<SceneObject>,
+    shader: ReloadableShader,
+    pub skybox: Option<Skybox>,
+    pub selected: Option<Pick>,
     pub debug_camera: Camera,
+    /// stops the debug camera in front of geometry, builds the bvh of every object on first use
+    pub camera_collision: bool,
 }
 
 impl Scene {
-    pub fn new(window: &WindowContainer) -> Scene {
-        let mut target = RenderTarget::new(window.width, window.height);
-        target.new_texture(gl::COLOR_ATTACHMENT0);
+    pub fn new(width: u32, height: u32, model_path: &Path) -> Result<Scene> {
+        let mut target = RenderTarget::new(width, height);
+        // lighting happens in linear space and may go above 1, the presenter maps it to the display
+        target.new_texture_format(gl::COLOR_ATTACHMENT0, TextureFormat::RGBA16F);
+        target.new_texture_format(OBJECT_ID_ATTACHMENT, TextureFormat::R32UI);
         target.new_buffer(gl::DEPTH_STENCIL_ATTACHMENT);
+        target.finish()?;
+        let presenter = Presenter::new(width, height)?;
 
-        let obj_file = "res/model/sponza/sponza.obj";
-        let (models, materials) = tobj::load_obj(
-            &obj_file,
-            &tobj::LoadOptions {
-                triangulate: true,
-                ignore_points: true,
-                single_index: true,
-                ignore_lines: true,
-            },
-        ).unwrap();
+        let shader = ReloadableShader::from_toml(Path::new("res/shader/lit.toml"))?;
+        shader.shader().reflect().validate_layout(&LitVertex::layout())?;
 
-        let model: Model<TexVertex> = Model::from_textured_tobj(models,materials.unwrap());
-
-        let shader = Shader::from_toml(Path::new("res/shader/textured.toml"));
-
-
-
-        Scene {
+        let mut scene = Scene {
             target,
-            model,
+            presenter,
+            assets: AssetManager::new(),
+            objects: Vec::new(),
             shader,
+            skybox: None,
+            selected: None,
+            camera_collision: false,
             debug_camera: Camera::new(55.0, 60.0),
-        }
+        };
+        scene.add_object(model_path, Mat4::from_translation(Vec3::new(0.0, 0.0, -1.0)))?;
+        Ok(scene)
+    }
+
+    /// Adds another instance of a model, files that are already loaded are shared.
+    pub fn add_object(&mut self, path: &Path, transform: Mat4) -> Result<()> {
+        let model = self.assets.model::<LitVertex>(path)?;
+        self.objects.push(SceneObject { model, transform });
+        Ok(())
     }
-    pub fn render(&mut self, renderer: &mut RenderPath) {
+    /// Camera input is only read with a `window`, headless renders keep the camera still.
+    pub fn render(&mut self, window: Option<&WindowContainer>) {
         self.target.bind();
+        unsafe {
+            gl::Viewport(0, 0, self.target.width() as i32, self.target.height() as i32);
+        }
 
-        if renderer.window.window.get_mouse_button(MouseButton::Button2) == Action::Press {
-            self.debug_camera.yaw -= renderer.window.delta_x * 0.1;
-            self.debug_camera.pitch -= renderer.window.delta_y * 0.1;
+        if let Some(window) = window {
+            if window.window.get_mouse_button(MouseButton::Button2) == Action::Press {
+                self.debug_camera.yaw -= window.delta_x * 0.1;
+                self.debug_camera.pitch -= window.delta_y * 0.1;
+            }
         }
 
+        // the target is linear, so the old srgb clear color is converted to keep the same look
+        let clear = srgb_to_linear(Vec3::new(0.1, 0.3, 0.51));
         unsafe {
-            gl::ClearColor(0.1, 0.3, 0.51, 1.0);
+            gl::ClearColor(clear.x, clear.y, clear.z, 1.0);
             gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
         }
-        self.shader.bind();
+        self.target.clear_uint(OBJECT_ID_ATTACHMENT, 0);
+
+        let previous = self.debug_camera.position;
+        if let Some(window) = window {
+            Scene::handle_keys(window, &mut self.debug_camera, 0.05);
+        }
+        if self.camera_collision {
+            self.collide_camera(previous);
+        }
 
-        Scene::handle_keys(&renderer.window, &mut self.debug_camera, 0.05);
+        self.shader.poll();
+        let shader = self.shader.shader();
+        shader.bind();
 
-        let projection = renderer.projection(&self.debug_camera);
+        let projection = self.debug_camera.projection(self.aspect());
         let view = self.debug_camera.matrix();
-        let model = Mat4::from_scale_rotation_translation(Vec3::splat(1.0),
-                                                          Quat::from_euler(
-                                                              EulerRot::XYZ, 0.0, 0.0,
-                                                              (renderer.window.glfw.get_time() * 0.00) as f32),
-                                                          Vec3::new(0.0, 0.0, -1.0));
-        self.shader.load_mat4("projection", &projection);
-        self.shader.load_mat4("view", &view);
-        self.shader.load_mat4("model", &model);
+        shader.load_mat4("projection", &projection);
+        shader.load_mat4("view", &view);
+        shader.load_vec3("lightDirection", &Vec3::new(-0.3, -1.0, -0.2));
+        shader.load_vec3("viewPosition", &self.debug_camera.position);
 
         shader_mode::fill();
         unsafe {
@@ -81,11 +168,129 @@ impl Scene {
             gl::CullFace(gl::BACK);
         }
 
-        self.model.render();
+        for (index, object) in self.objects.iter().enumerate() {
+            shader.load_mat4("model", &object.transform);
+            object.model.render_with_ids(shader, |mesh| Pick { object: index, mesh }.id().unwrap_or(0));
+        }
+
+        shader.unbind();
 
-        self.shader.unbind();
+        if let Some(skybox) = self.skybox.as_mut().filter(|x| x.enabled) {
+            skybox.render(&projection, &view);
+        }
 
         self.target.unbind();
+
+        if let Some(color) = self.target.get(gl::COLOR_ATTACHMENT0).and_then(|x| x.as_texture()) {
+            self.presenter.present(color);
+        }
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.target.width() as f32 / self.target.height() as f32
+    }
+
+    /// World space bounds of every object
+    pub fn bounds(&self) -> Aabb {
+        self.objects.iter()
+            .map(|x| x.model.bounds().transform(&x.transform))
+            .fold(Aabb::empty(), Aabb::union)
+    }
+
+    pub fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        self.objects.iter()
+            .map(|x| x.model.bounding_sphere().transform(&x.transform))
+            .reduce(BoundingSphere::union)
+    }
+
+    /// Closest hit of a world space ray over all objects, with the index of the object.
+    /// The distance and point of the hit are in world space as well.
+    pub fn raycast(&self, ray: &Ray) -> Option<(usize, Hit)> {
+        let mut best: Option<(usize, Hit)> = None;
+        for (index, object) in self.objects.iter().enumerate() {
+            if let Some(hit) = object.model.raycast(&object.local_ray(ray)) {
+                let hit = object.world_hit(ray, hit);
+                if best.is_none_or(|(_, x)| hit.t < x.t) {
+                    best = Some((index, hit));
+                }
+            }
+        }
+        best
+    }
+
+    /// Every hit of a world space ray over all objects, sorted by world space distance.
+    pub fn raycast_all(&self, ray: &Ray) -> Vec<(usize, Hit)> {
+        let mut hits: Vec<(usize, Hit)> = self.objects.iter().enumerate().flat_map(|(index, object)| {
+            object.model.raycast_all(&object.local_ray(ray))
+                  .into_iter()
+                  .map(move |hit| (index, object.world_hit(ray, hit)))
+        }).collect();
+        hits.sort_by(|(_, a), (_, b)| a.t.total_cmp(&b.t));
+        hits
+    }
+
+    /// Moves the camera back along its last step if that went through geometry.
+    fn collide_camera(&mut self, from: Vec3) {
+        let step = self.debug_camera.position - from;
+        let length = step.length();
+        if length <= f32::EPSILON {
+            return;
+        }
+        let radius = self.bounding_sphere().map_or(0.0, |x| x.radius * CAMERA_RADIUS);
+        if let Some((_, hit)) = self.raycast(&Ray::new(from, step)) {
+            if hit.t < length + radius {
+                self.debug_camera.position = from + step / length * (hit.t - radius).max(0.0);
+            }
+        }
+    }
+
+    pub fn picking(&self) -> bool {
+        self.target.has(OBJECT_ID_ATTACHMENT)
+    }
+
+    /// Adds or removes the object id attachment, without it `pick` always returns `None`.
+    pub fn set_picking(&mut self, enabled: bool) -> Result<()> {
+        if enabled == self.picking() {
+            return Ok(());
+        }
+        if enabled {
+            self.target.new_texture_format(OBJECT_ID_ATTACHMENT, TextureFormat::R32UI);
+        } else {
+            self.target.remove(OBJECT_ID_ATTACHMENT);
+            self.selected = None;
+        }
+        self.target.finish()
+    }
+
+    /// The mesh drawn at a scene target pixel in the last frame, origin at the bottom left.
+    pub fn pick(&self, x: u32, y: u32) -> Option<Pick> {
+        let pick = Pick::from_id(self.target.read_uint(OBJECT_ID_ATTACHMENT, x, y)?)?;
+        // the ids are from the last frame, objects may be gone since
+        self.objects.get(pick.object)?.model.meshes.get(pick.mesh)?;
+        Some(pick)
+    }
+
+    /// Rebuilds the scene and output targets at the new framebuffer size.
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<()> {
+        self.target.resize(width, height)?;
+        self.presenter.target.resize(width, height)
+    }
+
+    /// Replaces the skybox, the old one is kept if loading fails.
+    pub fn load_skybox(&mut self, path: &Path, color_space: ColorSpace) -> Result<()> {
+        self.skybox = Some(Skybox::load(path, color_space)?);
+        Ok(())
+    }
+
+    pub fn shader(&self) -> &Shader {
+        self.shader.shader()
+    }
+
+    /// The first failed reload of the lit, present or skybox shader
+    pub fn shader_error(&self) -> Option<&str> {
+        self.shader.error.as_deref()
+            .or_else(|| self.presenter.shader_error())
+            .or_else(|| self.skybox.as_ref().and_then(|x| x.shader_error()))
     }
 
     pub fn handle_keys(handle: &WindowContainer, camera: &mut Camera, delta_time: f32) {
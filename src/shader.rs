@@ -1,18 +1,38 @@
 extern crate gl;
 
 
-use std::ffi::{CStr, CString};
-use std::fs::{self, File, OpenOptions};
-use std::io::Write;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+use std::fs;
+use std::io;
 use std::path::Path;
 use std::ptr;
 
-use gl::types::{self, GLchar, GLint};
+use gl::types::{self, GLchar, GLint, GLsizei};
+use glam::{IVec2, IVec3, IVec4, Mat3, Mat4, Vec2, Vec3, Vec4};
 
+use crate::error::{Error, Result};
+use crate::reflection::Reflection;
 use crate::gl::types::GLuint;
 
+/// Fragment outputs by name and the draw buffer they go to, `FragColor` to the first color
+/// attachment of a target and the picking ids to the second. Toml shaders can't write layout
+/// qualifiers, so this is how their outputs get a location no matter the declaration order.
+pub const FRAGMENT_OUTPUTS: [(&str, u32); 2] = [("FragColor", 0), ("ObjectId", 1)];
+
 pub struct Shader {
     program: GLuint,
+    uniforms: HashMap<String, Uniform>,
+    reflection: Reflection,
+    missing: RefCell<HashSet<String>>,
+}
+
+#[derive(Clone, Copy)]
+pub struct Uniform {
+    pub location: GLint,
+    /// array length, 1 for non array uniforms
+    pub size: GLint,
 }
 
 impl Shader {
@@ -28,35 +48,199 @@ impl Shader {
         }
     }
 
-    pub fn delete(&self) {
-        unsafe {
-            gl::DeleteProgram(self.program);
+    fn location(&self, name: &str) -> Option<GLint> {
+        self.array_location(name, 1).map(|(location, _)| location)
+    }
+
+    /// Location and how many of `count` values fit into the uniform array
+    fn array_location(&self, name: &str, count: usize) -> Option<(GLint, GLsizei)> {
+        match self.uniforms.get(name) {
+            Some(uniform) => Some((uniform.location, count.min(uniform.size as usize) as GLsizei)),
+            None => {
+                if !self.missing.borrow().contains(name) {
+                    self.missing.borrow_mut().insert(name.to_owned());
+                }
+                None
+            }
         }
     }
 
-    pub fn load_mat4(&self, name: &str, mat: &glam::Mat4) {
-        unsafe {
-            let c_str = CString::new(name).unwrap();
-            let location = gl::GetUniformLocation(self.program, c_str.as_ptr());
+    /// Uniforms that were set but don't exist in the program, usually because
+    /// the compiler optimized them out, sorted by name
+    pub fn missing_uniforms(&self) -> Vec<String> {
+        let mut missing: Vec<String> = self.missing.borrow().iter().cloned().collect();
+        missing.sort_unstable();
+        missing
+    }
+
+    /// Active attributes, uniforms and uniform blocks, queried once after linking
+    pub fn reflect(&self) -> &Reflection {
+        &self.reflection
+    }
 
-            gl::UniformMatrix4fv(location, 1, gl::FALSE, &mat.to_cols_array()[0]);
+}
+
+/// Setters for every uniform type, the bundled shaders don't use all of them.
+#[allow(dead_code)]
+impl Shader {
+    pub fn load_float(&self, name: &str, value: f32) {
+        if let Some(location) = self.location(name) {
+            unsafe {
+                gl::Uniform1f(location, value);
+            }
+        }
+    }
+
+    pub fn load_int(&self, name: &str, value: i32) {
+        if let Some(location) = self.location(name) {
+            unsafe {
+                gl::Uniform1i(location, value);
+            }
+        }
+    }
+
+    pub fn load_uint(&self, name: &str, value: u32) {
+        if let Some(location) = self.location(name) {
+            unsafe {
+                gl::Uniform1ui(location, value);
+            }
         }
     }
 
-    pub fn from_toml(location: &Path) -> Shader {
-        let toml = fs::read_to_string(location).unwrap();
-        let (vertex, fragment, _config) = dsa_lib::compile_toml(toml.as_str()).unwrap();
-        let vert_link = Shader::compile(gl::VERTEX_SHADER, vertex.as_str());
-        let frag_link = Shader::compile(gl::FRAGMENT_SHADER, fragment.as_str());
-        let handle = Shader::link(frag_link, vert_link);
-        return Shader { program: handle };
+    pub fn load_vec2(&self, name: &str, vec: &Vec2) {
+        if let Some(location) = self.location(name) {
+            unsafe {
+                gl::Uniform2f(location, vec.x, vec.y);
+            }
+        }
     }
 
-    pub fn load(location: &Path) -> Shader {
+    pub fn load_vec3(&self, name: &str, vec: &Vec3) {
+        if let Some(location) = self.location(name) {
+            unsafe {
+                gl::Uniform3f(location, vec.x, vec.y, vec.z);
+            }
+        }
+    }
+
+    pub fn load_vec4(&self, name: &str, vec: &Vec4) {
+        if let Some(location) = self.location(name) {
+            unsafe {
+                gl::Uniform4f(location, vec.x, vec.y, vec.z, vec.w);
+            }
+        }
+    }
+
+    pub fn load_ivec2(&self, name: &str, vec: &IVec2) {
+        if let Some(location) = self.location(name) {
+            unsafe {
+                gl::Uniform2i(location, vec.x, vec.y);
+            }
+        }
+    }
+
+    pub fn load_ivec3(&self, name: &str, vec: &IVec3) {
+        if let Some(location) = self.location(name) {
+            unsafe {
+                gl::Uniform3i(location, vec.x, vec.y, vec.z);
+            }
+        }
+    }
+
+    pub fn load_ivec4(&self, name: &str, vec: &IVec4) {
+        if let Some(location) = self.location(name) {
+            unsafe {
+                gl::Uniform4i(location, vec.x, vec.y, vec.z, vec.w);
+            }
+        }
+    }
+
+    pub fn load_mat3(&self, name: &str, mat: &Mat3) {
+        if let Some(location) = self.location(name) {
+            unsafe {
+                gl::UniformMatrix3fv(location, 1, gl::FALSE, &mat.to_cols_array()[0]);
+            }
+        }
+    }
+
+    pub fn load_mat4(&self, name: &str, mat: &Mat4) {
+        if let Some(location) = self.location(name) {
+            unsafe {
+                gl::UniformMatrix4fv(location, 1, gl::FALSE, &mat.to_cols_array()[0]);
+            }
+        }
+    }
+
+    /// Binds a sampler uniform to a texture unit, `unit` 0 is `gl::TEXTURE0`
+    pub fn load_sampler(&self, name: &str, unit: u32) {
+        self.load_int(name, unit as i32);
+    }
+
+    pub fn load_float_array(&self, name: &str, values: &[f32]) {
+        if let Some((location, count)) = self.array_location(name, values.len()) {
+            unsafe {
+                gl::Uniform1fv(location, count, values.as_ptr());
+            }
+        }
+    }
+
+    pub fn load_int_array(&self, name: &str, values: &[i32]) {
+        if let Some((location, count)) = self.array_location(name, values.len()) {
+            unsafe {
+                gl::Uniform1iv(location, count, values.as_ptr());
+            }
+        }
+    }
+
+    pub fn load_vec3_array(&self, name: &str, values: &[Vec3]) {
+        if let Some((location, count)) = self.array_location(name, values.len()) {
+            let data: Vec<f32> = values.iter().flat_map(|x| x.to_array()).collect();
+            unsafe {
+                gl::Uniform3fv(location, count, data.as_ptr());
+            }
+        }
+    }
+
+    pub fn load_vec4_array(&self, name: &str, values: &[Vec4]) {
+        if let Some((location, count)) = self.array_location(name, values.len()) {
+            let data: Vec<f32> = values.iter().flat_map(|x| x.to_array()).collect();
+            unsafe {
+                gl::Uniform4fv(location, count, data.as_ptr());
+            }
+        }
+    }
+
+    pub fn load_mat4_array(&self, name: &str, values: &[Mat4]) {
+        if let Some((location, count)) = self.array_location(name, values.len()) {
+            let data: Vec<f32> = values.iter().flat_map(|x| x.to_cols_array()).collect();
+            unsafe {
+                gl::UniformMatrix4fv(location, count, gl::FALSE, data.as_ptr());
+            }
+        }
+    }
+
+}
+
+impl Shader {
+    pub fn from_toml(location: &Path) -> Result<Shader> {
+        let toml = fs::read_to_string(location).map_err(|e| Error::io(location, e))?;
+        let (vertex, fragment, _config) = dsa_lib::compile_toml(toml.as_str())
+            .map_err(|e| Error::ShaderConfig {
+                path: location.to_path_buf(),
+                message: e.to_string(),
+            })?;
+        Shader::from_sources(vertex.as_str(), fragment.as_str())
+    }
+
+    pub fn load(location: &Path) -> Result<Shader> {
         if !location.exists() {
-            panic!("Path {} does not exist", location.to_str().unwrap());
+            return Err(Error::not_found(location));
         }
-        let name = location.file_name().unwrap().to_str().unwrap();
+        // `name/name.frag`, so "." or ".." can't be resolved without knowing the working directory
+        let name = location.file_name().and_then(|x| x.to_str()).ok_or_else(|| {
+            Error::io(location, io::Error::new(io::ErrorKind::InvalidInput,
+                                               "shader directory needs a utf-8 name"))
+        })?;
         let mut str = "".to_owned();
         str.push_str(name);
 
@@ -64,31 +248,49 @@ impl Shader {
         frag_path.push_str(".frag");
         let frag = location.join(Path::new(frag_path.as_str()));
         if !frag.exists() {
-            panic!("Frag Shader does not exist");
+            return Err(Error::not_found(&frag));
         }
 
         let mut vert_path = str.clone();
         vert_path.push_str(".vert");
         let vert = location.join(Path::new(vert_path.as_str()));
         if !vert.exists() {
-            panic!("Frag Shader does not exist");
+            return Err(Error::not_found(&vert));
         }
 
-        let fragment_src = fs::read_to_string(frag).expect("Failed to read fragment shader");
-        let vertex_src = fs::read_to_string(vert).expect("Failed to read vertex shader");
+        let fragment_src = fs::read_to_string(&frag).map_err(|e| Error::io(&frag, e))?;
+        let vertex_src = fs::read_to_string(&vert).map_err(|e| Error::io(&vert, e))?;
 
         // println!("Fragment Src: \n{}", fragment_src);
         // println!("Vertex Src: \n{}", vertex_src);
 
-        let vert_link = Shader::compile(gl::VERTEX_SHADER, vertex_src.as_str());
-        let frag_link = Shader::compile(gl::FRAGMENT_SHADER, fragment_src.as_str());
+        Shader::from_sources(vertex_src.as_str(), fragment_src.as_str())
+    }
 
-        let handle = Shader::link(frag_link, vert_link);
+    pub fn from_sources(vertex_src: &str, fragment_src: &str) -> Result<Shader> {
+        let vert_link = Shader::compile(gl::VERTEX_SHADER, vertex_src)?;
+        let frag_link = match Shader::compile(gl::FRAGMENT_SHADER, fragment_src) {
+            Ok(handle) => handle,
+            Err(error) => {
+                unsafe {
+                    gl::DeleteShader(vert_link);
+                }
+                return Err(error);
+            }
+        };
+
+        let handle = Shader::link(frag_link, vert_link)?;
 
-        return Shader { program: handle };
+        let reflection = Reflection::new(handle);
+        Ok(Shader {
+            program: handle,
+            uniforms: Shader::uniform_cache(&reflection),
+            reflection,
+            missing: RefCell::new(HashSet::new()),
+        })
     }
 
-    fn compile(type_: types::GLenum, src: &str) -> u32 {
+    fn compile(type_: types::GLenum, src: &str) -> Result<u32> {
         unsafe {
             let handle = gl::CreateShader(type_);
             let c_str = CString::new(src.as_bytes()).unwrap();
@@ -100,55 +302,109 @@ impl Shader {
             if status != (gl::TRUE as GLint) {
                 let mut len = 0;
                 gl::GetShaderiv(handle, gl::INFO_LOG_LENGTH, &mut len);
-                let mut buf = Vec::with_capacity(len as usize);
-                buf.set_len((len as usize) - 1);
+                let mut buf = vec![0u8; len.max(1) as usize];
                 gl::GetShaderInfoLog(
                     handle,
                     len,
                     ptr::null_mut(),
                     buf.as_mut_ptr() as *mut GLchar,
                 );
-                panic!(
-                    "{}",
-                    std::str::from_utf8(&buf)
-                        .ok()
-                        .expect("ShaderInfoLog not valid utf8")
-                );
+                gl::DeleteShader(handle);
+                return Err(Error::ShaderCompile {
+                    stage: type_,
+                    log: info_log(buf),
+                });
             }
-            handle
+            Ok(handle)
         }
     }
 
-    fn link(fragment_shader: u32, vertex_shader: u32) -> u32 {
+    /// Outputs named in `FRAGMENT_OUTPUTS` get their fixed location, explicit layout locations win.
+    fn link(fragment_shader: u32, vertex_shader: u32) -> Result<u32> {
         unsafe {
             let shader_program = gl::CreateProgram();
             gl::AttachShader(shader_program, vertex_shader);
             gl::AttachShader(shader_program, fragment_shader);
+            for (name, location) in FRAGMENT_OUTPUTS {
+                let name = CString::new(name).unwrap();
+                gl::BindFragDataLocation(shader_program, location, name.as_ptr());
+            }
             gl::LinkProgram(shader_program);
+            gl::DeleteShader(vertex_shader);
+            gl::DeleteShader(fragment_shader);
             // check for linking errors
             let mut status = gl::FALSE as GLint;
             gl::GetProgramiv(shader_program, gl::LINK_STATUS, &mut status);
             if status != (gl::TRUE as GLint) {
                 let mut len: GLint = 0;
                 gl::GetProgramiv(shader_program, gl::INFO_LOG_LENGTH, &mut len);
-                let mut buf = Vec::with_capacity(len as usize);
-                buf.set_len((len as usize) - 1); // subtract 1 to skip the trailing null character
+                let mut buf = vec![0u8; len.max(1) as usize];
                 gl::GetProgramInfoLog(
                     shader_program,
                     len,
                     ptr::null_mut(),
                     buf.as_mut_ptr() as *mut GLchar,
                 );
-                panic!(
-                    "{}",
-                    std::str::from_utf8(&buf)
-                        .ok()
-                        .expect("ProgramInfoLog not valid utf8")
-                );
+                gl::DeleteProgram(shader_program);
+                return Err(Error::ShaderLink { log: info_log(buf) });
+            }
+            Ok(shader_program)
+        }
+    }
+}
+
+impl Drop for Shader {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteProgram(self.program);
+        }
+    }
+}
+
+impl Shader {
+    /// Arrays are reported as `name[0]` and are stored under both names.
+    fn uniform_cache(reflection: &Reflection) -> HashMap<String, Uniform> {
+        let mut uniforms = HashMap::new();
+        for uniform in reflection.uniforms.iter() {
+            // uniforms inside of uniform blocks have no location
+            if uniform.location < 0 {
+                continue;
+            }
+            let entry = Uniform {
+                location: uniform.location,
+                size: uniform.size,
+            };
+            if let Some(base) = uniform.name.strip_suffix("[0]") {
+                uniforms.insert(base.to_owned(), entry);
+            }
+            uniforms.insert(uniform.name.clone(), entry);
+        }
+        uniforms
+    }
+}
+
+fn info_log(mut buf: Vec<u8>) -> String {
+    // skip the trailing null character
+    while buf.last() == Some(&0) {
+        buf.pop();
+    }
+    String::from_utf8_lossy(&buf).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_without_directory_name() {
+        for location in [".", "res/shader/.."] {
+            match Shader::load(Path::new(location)) {
+                Err(Error::Io { path, source }) => {
+                    assert_eq!(path, Path::new(location));
+                    assert_eq!(source.kind(), io::ErrorKind::InvalidInput);
+                }
+                other => panic!("{}: {:?}", location, other.map(|_| ())),
             }
-            gl::DeleteShader(vertex_shader);
-            gl::DeleteShader(fragment_shader);
-            shader_program
         }
     }
 }
@@ -0,0 +1,105 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use glam::{Mat3, Mat4};
+
+use crate::assets::{load_cubemap, load_equirectangular};
+use crate::error::{Error, Result};
+use crate::reload::ReloadableShader;
+use crate::textures::{ColorSpace, State, TextureCube};
+
+const FACE_NAMES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+const EQUIRECTANGULAR_FACE_SIZE: u32 = 1024;
+
+/// A cubemap drawn behind everything, only rotating with the camera.
+pub struct Skybox {
+    texture: TextureCube,
+    shader: ReloadableShader,
+    /// the fullscreen triangle has no attributes, but core profile needs a vao bound
+    vao: u32,
+    pub path: PathBuf,
+    pub enabled: bool,
+    pub intensity: f32,
+}
+
+impl Skybox {
+    /// A directory is read as six faces named `px`, `nx`, `py`, `ny`, `pz` and `nz`
+    /// with any image extension, a file as an equirectangular panorama.
+    pub fn load(path: &Path, color_space: ColorSpace) -> Result<Skybox> {
+        let texture = if path.is_dir() {
+            let faces = FACE_NAMES.iter()
+                                  .map(|name| find_face(path, name))
+                                  .collect::<Result<Vec<_>>>()?;
+            load_cubemap([&faces[0], &faces[1], &faces[2], &faces[3], &faces[4], &faces[5]],
+                         color_space)?
+        } else if path.is_file() {
+            load_equirectangular(path, EQUIRECTANGULAR_FACE_SIZE, color_space)?
+        } else {
+            return Err(Error::not_found(path));
+        };
+
+        let shader = ReloadableShader::load(Path::new("res/shader/skybox"))?;
+        let mut vao = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+        }
+        Ok(Skybox {
+            texture,
+            shader,
+            vao,
+            path: path.to_path_buf(),
+            enabled: true,
+            intensity: 1.0,
+        })
+    }
+
+    pub fn shader_error(&self) -> Option<&str> {
+        self.shader.error.as_deref()
+    }
+
+    /// Has to run after the opaque geometry, it only fills pixels still at the far plane.
+    pub fn render(&mut self, projection: &Mat4, view: &Mat4) {
+        self.shader.poll();
+        let shader = self.shader.shader();
+        let rotation = Mat4::from_mat3(Mat3::from_mat4(*view));
+        let inverse = (*projection * rotation).inverse();
+
+        shader.bind();
+        shader.load_mat4("inverseViewProjection", &inverse);
+        shader.load_float("intensity", self.intensity);
+        shader.load_sampler("sky", 0);
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::DepthFunc(gl::LEQUAL);
+            gl::DepthMask(gl::FALSE);
+        }
+        self.texture.bind();
+        unsafe {
+            gl::BindVertexArray(self.vao);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            gl::BindVertexArray(0);
+            gl::DepthMask(gl::TRUE);
+            gl::DepthFunc(gl::LESS);
+        }
+        self.texture.unbind();
+        shader.unbind();
+    }
+}
+
+impl Drop for Skybox {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
+    }
+}
+
+fn find_face(directory: &Path, name: &str) -> Result<PathBuf> {
+    let entries = fs::read_dir(directory).map_err(|e| Error::io(directory, e))?;
+    entries.filter_map(|x| x.ok())
+           .map(|x| x.path())
+           .find(|x| x.is_file() && x.file_stem()
+                                     .and_then(|x| x.to_str())
+                                     .is_some_and(|x| x.eq_ignore_ascii_case(name)))
+           .ok_or_else(|| Error::not_found(&directory.join(name)))
+}
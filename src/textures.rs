@@ -1,18 +1,215 @@
+use std::borrow::Cow;
+use std::f32::consts::PI;
 use std::os::raw::c_void;
 
 use gl::types::GLenum;
+use glam::{Vec3, Vec4};
+use image::{DynamicImage, Rgba32FImage};
 
+use crate::error::{Error, Result};
+use crate::material::srgb_to_linear;
+
+/// Whether a texture holds colors, which are stored in srgb and decoded when sampled,
+/// or data like normals, heights and masks that has to stay linear.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+/// Internal format of a texture together with the layout of the uploaded pixel data.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TextureFormat {
+    R8,
+    RG8,
+    RGB8,
+    RGBA8,
+    SRGB8,
+    SRGB8Alpha8,
+    R16,
+    RG16,
+    RGB16,
+    RGBA16,
+    /// uploaded from f32 data, stored as half floats
+    RGBA16F,
+    RGB32F,
+    RGBA32F,
+    /// for shadow maps, uploaded from f32 data
+    #[allow(dead_code)]
+    Depth32F,
+    /// unnormalized integers, like object ids, sampled with `usampler2D`
+    R32UI,
+}
+
+impl TextureFormat {
+    /// The format matching the pixel layout of a decoded image
+    pub fn of(image: &DynamicImage) -> TextureFormat {
+        match image {
+            DynamicImage::ImageLuma8(_) => TextureFormat::R8,
+            DynamicImage::ImageLumaA8(_) => TextureFormat::RG8,
+            DynamicImage::ImageRgb8(_) => TextureFormat::RGB8,
+            DynamicImage::ImageRgba8(_) => TextureFormat::RGBA8,
+            DynamicImage::ImageLuma16(_) => TextureFormat::R16,
+            DynamicImage::ImageLumaA16(_) => TextureFormat::RG16,
+            DynamicImage::ImageRgb16(_) => TextureFormat::RGB16,
+            DynamicImage::ImageRgba16(_) => TextureFormat::RGBA16,
+            DynamicImage::ImageRgb32F(_) => TextureFormat::RGB32F,
+            DynamicImage::ImageRgba32F(_) => TextureFormat::RGBA32F,
+            _ => TextureFormat::RGBA8,
+        }
+    }
+
+    /// The srgb variant of 8 bit color formats, everything else is linear already.
+    pub fn with_color_space(self, color_space: ColorSpace) -> TextureFormat {
+        match (self, color_space) {
+            (TextureFormat::RGB8, ColorSpace::Srgb) => TextureFormat::SRGB8,
+            (TextureFormat::RGBA8, ColorSpace::Srgb) => TextureFormat::SRGB8Alpha8,
+            (TextureFormat::SRGB8, ColorSpace::Linear) => TextureFormat::RGB8,
+            (TextureFormat::SRGB8Alpha8, ColorSpace::Linear) => TextureFormat::RGBA8,
+            (format, _) => format,
+        }
+    }
+
+    /// Integer formats can't be filtered, only `NEAREST` works
+    pub fn is_integer(&self) -> bool {
+        *self == TextureFormat::R32UI
+    }
+
+    pub fn internal_format(&self) -> GLenum {
+        match self {
+            TextureFormat::R8 => gl::R8,
+            TextureFormat::RG8 => gl::RG8,
+            TextureFormat::RGB8 => gl::RGB8,
+            TextureFormat::RGBA8 => gl::RGBA8,
+            TextureFormat::SRGB8 => gl::SRGB8,
+            TextureFormat::SRGB8Alpha8 => gl::SRGB8_ALPHA8,
+            TextureFormat::R16 => gl::R16,
+            TextureFormat::RG16 => gl::RG16,
+            TextureFormat::RGB16 => gl::RGB16,
+            TextureFormat::RGBA16 => gl::RGBA16,
+            TextureFormat::RGBA16F => gl::RGBA16F,
+            TextureFormat::RGB32F => gl::RGB32F,
+            TextureFormat::RGBA32F => gl::RGBA32F,
+            TextureFormat::Depth32F => gl::DEPTH_COMPONENT32F,
+            TextureFormat::R32UI => gl::R32UI,
+        }
+    }
+
+    pub fn channels(&self) -> u32 {
+        match self {
+            TextureFormat::R8 | TextureFormat::R16 | TextureFormat::Depth32F |
+            TextureFormat::R32UI => 1,
+            TextureFormat::RG8 | TextureFormat::RG16 => 2,
+            TextureFormat::RGB8 | TextureFormat::SRGB8 | TextureFormat::RGB16 |
+            TextureFormat::RGB32F => 3,
+            TextureFormat::RGBA8 | TextureFormat::SRGB8Alpha8 | TextureFormat::RGBA16 |
+            TextureFormat::RGBA16F | TextureFormat::RGBA32F => 4,
+        }
+    }
+
+    /// `format` argument of `glTexImage2D`
+    pub fn format(&self) -> GLenum {
+        match self {
+            TextureFormat::Depth32F => return gl::DEPTH_COMPONENT,
+            TextureFormat::R32UI => return gl::RED_INTEGER,
+            _ => {}
+        }
+        match self.channels() {
+            1 => gl::RED,
+            2 => gl::RG,
+            3 => gl::RGB,
+            _ => gl::RGBA,
+        }
+    }
+
+    /// `type` argument of `glTexImage2D`
+    pub fn data_type(&self) -> GLenum {
+        match self {
+            TextureFormat::R16 | TextureFormat::RG16 | TextureFormat::RGB16 |
+            TextureFormat::RGBA16 => gl::UNSIGNED_SHORT,
+            TextureFormat::RGBA16F | TextureFormat::RGB32F | TextureFormat::RGBA32F |
+            TextureFormat::Depth32F => gl::FLOAT,
+            TextureFormat::R32UI => gl::UNSIGNED_INT,
+            _ => gl::UNSIGNED_BYTE,
+        }
+    }
+
+    pub fn bytes_per_pixel(&self) -> u32 {
+        let component = match self.data_type() {
+            gl::UNSIGNED_SHORT => 2,
+            gl::FLOAT | gl::UNSIGNED_INT => 4,
+            _ => 1,
+        };
+        self.channels() * component
+    }
+}
+
+/// The pixel data of a decoded image and the format to upload it with.
+/// Color types without a matching format are converted to rgba8.
+fn image_data(image: &DynamicImage, color_space: ColorSpace) -> (Cow<'_, [u8]>, TextureFormat) {
+    let format = TextureFormat::of(image).with_color_space(color_space);
+    match format {
+        // only the color types in `TextureFormat::of` have a matching format
+        TextureFormat::RGBA8 | TextureFormat::SRGB8Alpha8
+        if !matches!(image, DynamicImage::ImageRgba8(_)) => {
+            (Cow::Owned(image.to_rgba8().into_raw()), format)
+        }
+        _ => (Cow::Borrowed(image.as_bytes()), format),
+    }
+}
+
+fn tex_parameter(target: GLenum, parameter: GLenum, state: GLenum) {
+    unsafe {
+        gl::TexParameteri(target, parameter, state as i32);
+    }
+}
+
+/// Shows gray and gray alpha images as gray instead of red
+fn swizzle_gray(target: GLenum, format: TextureFormat) {
+    match format {
+        TextureFormat::Depth32F | TextureFormat::R32UI => {}
+        _ if format.channels() == 1 => {
+            tex_parameter(target, gl::TEXTURE_SWIZZLE_G, gl::RED);
+            tex_parameter(target, gl::TEXTURE_SWIZZLE_B, gl::RED);
+        }
+        _ if format.channels() == 2 => {
+            tex_parameter(target, gl::TEXTURE_SWIZZLE_G, gl::RED);
+            tex_parameter(target, gl::TEXTURE_SWIZZLE_B, gl::RED);
+            tex_parameter(target, gl::TEXTURE_SWIZZLE_A, gl::GREEN);
+        }
+        _ => {}
+    }
+}
+
+/// Largest alignment `GL_UNPACK_ALIGNMENT` accepts that evenly divides a row,
+/// odd widths of rgb8 images need 1.
+fn row_alignment(row_bytes: u32) -> i32 {
+    [8, 4, 2, 1].into_iter().find(|x| row_bytes.is_multiple_of(*x)).unwrap_or(1) as i32
+}
+
+/// Owns the gl texture and deletes it when dropped,
+/// use `Rc<Texture2D>` to share it.
 pub struct Texture2D {
-    pub id: u32,
-    pub width: u32,
-    pub height: u32,
+    id: u32,
+    width: u32,
+    height: u32,
 }
 
 impl Texture2D {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
     pub fn tex_parameter(&self, parameter: GLenum, state: GLenum) {
-        unsafe {
-            gl::TexParameteri(gl::TEXTURE_2D, parameter, state as i32);
-        }
+        tex_parameter(gl::TEXTURE_2D, parameter, state);
     }
 
     pub fn gen_mipmaps(&self) {
@@ -21,17 +218,39 @@ impl Texture2D {
         }
     }
 
-    pub fn put_data(&self, data: *const c_void, format: GLenum) {
+    /// Uploads tightly packed pixels of the given format to level 0.
+    pub fn put_pixels(&self, data: &[u8], format: TextureFormat) {
+        let row_bytes = self.width * format.bytes_per_pixel();
+        assert!(data.len() >= (row_bytes * self.height) as usize,
+                "Not enough pixel data for the texture size");
         unsafe {
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, row_alignment(row_bytes));
             gl::TexImage2D(gl::TEXTURE_2D,
                            0,
-                           format as i32,
+                           format.internal_format() as i32,
                            self.width as i32,
                            self.height as i32,
                            0,
-                           format,
-                           gl::UNSIGNED_BYTE,
-                           data);
+                           format.format(),
+                           format.data_type(),
+                           data.as_ptr() as *const c_void);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+        }
+        swizzle_gray(gl::TEXTURE_2D, format);
+    }
+
+    /// Allocates level 0 without uploading anything, for render targets.
+    pub fn put_empty(&self, format: TextureFormat) {
+        unsafe {
+            gl::TexImage2D(gl::TEXTURE_2D,
+                           0,
+                           format.internal_format() as i32,
+                           self.width as i32,
+                           self.height as i32,
+                           0,
+                           format.format(),
+                           format.data_type(),
+                           std::ptr::null());
         }
     }
 
@@ -47,21 +266,53 @@ impl Texture2D {
         }
     }
 
-    pub fn from_rgb(data: &[u8], width: u32, height: u32) -> Texture2D {
-        unsafe {
-            let tex = Texture2D::new(width, height);
-            tex.bind();
-            tex.tex_parameter(gl::TEXTURE_WRAP_S, gl::REPEAT);
-            tex.tex_parameter(gl::TEXTURE_WRAP_T, gl::REPEAT);
+    pub fn from_pixels(data: &[u8], width: u32, height: u32, format: TextureFormat) -> Texture2D {
+        let tex = Texture2D::new(width, height);
+        tex.bind();
+        tex.tex_parameter(gl::TEXTURE_WRAP_S, gl::REPEAT);
+        tex.tex_parameter(gl::TEXTURE_WRAP_T, gl::REPEAT);
+
+        // only the defaults, a bound `Sampler` overrides them
+        tex.tex_parameter(gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR);
+        tex.tex_parameter(gl::TEXTURE_MAG_FILTER, gl::LINEAR);
 
-            tex.tex_parameter(gl::TEXTURE_MIN_FILTER, gl::LINEAR);
-            tex.tex_parameter(gl::TEXTURE_MAG_FILTER, gl::LINEAR);
+        tex.put_pixels(data, format);
 
-            tex.put_data(&data[0] as *const u8 as *const c_void, gl::RGB);
+        tex.gen_mipmaps();
+
+        tex
+    }
 
-            tex.gen_mipmaps();
+    /// Uploads the image with the internal format matching its color type.
+    /// Images are not flipped, gl expects the first row at the bottom.
+    pub fn from_image(image: &DynamicImage, color_space: ColorSpace) -> Texture2D {
+        let (data, format) = image_data(image, color_space);
+        Texture2D::from_pixels(&data, image.width(), image.height(), format)
+    }
+}
 
-            tex
+impl Texture2D {
+    /// A magenta and black checkerboard, used when a texture is missing
+    pub fn checkerboard(size: u32, cells: u32, color_space: ColorSpace) -> Texture2D {
+        let cell = (size / cells).max(1);
+        let mut data = Vec::with_capacity((size * size * 3) as usize);
+        for y in 0..size {
+            for x in 0..size {
+                if (x / cell + y / cell).is_multiple_of(2) {
+                    data.extend_from_slice(&[255, 0, 255]);
+                } else {
+                    data.extend_from_slice(&[0, 0, 0]);
+                }
+            }
+        }
+        Texture2D::from_pixels(&data, size, size, TextureFormat::RGB8.with_color_space(color_space))
+    }
+}
+
+impl Drop for Texture2D {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.id);
         }
     }
 }
@@ -80,12 +331,557 @@ impl State for Texture2D {
     }
 }
 
-pub trait Renderable {
-    fn render();
-    fn dispose();
+/// Layers of the same size and format, sampled with `sampler2DArray`.
+/// Used for sprite atlases and shadow cascades.
+#[allow(dead_code)]
+pub struct Texture2DArray {
+    id: u32,
+    width: u32,
+    height: u32,
+    layers: u32,
+    format: TextureFormat,
+}
+
+#[allow(dead_code)]
+impl Texture2DArray {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn layers(&self) -> u32 {
+        self.layers
+    }
+
+    pub fn tex_parameter(&self, parameter: GLenum, state: GLenum) {
+        tex_parameter(gl::TEXTURE_2D_ARRAY, parameter, state);
+    }
+
+    /// Allocates every layer, the contents are undefined until `put_layer`.
+    pub fn new(width: u32, height: u32, layers: u32, format: TextureFormat) -> Texture2DArray {
+        let mut id = 0;
+        unsafe {
+            gl::GenTextures(1, &mut id);
+        }
+        let texture = Texture2DArray {
+            id,
+            width,
+            height,
+            layers,
+            format,
+        };
+        texture.bind();
+        unsafe {
+            gl::TexImage3D(gl::TEXTURE_2D_ARRAY,
+                           0,
+                           format.internal_format() as i32,
+                           width as i32,
+                           height as i32,
+                           layers as i32,
+                           0,
+                           format.format(),
+                           format.data_type(),
+                           std::ptr::null());
+        }
+        let filter = if format == TextureFormat::Depth32F { gl::NEAREST } else { gl::LINEAR };
+        texture.tex_parameter(gl::TEXTURE_MIN_FILTER, filter);
+        texture.tex_parameter(gl::TEXTURE_MAG_FILTER, filter);
+        texture.tex_parameter(gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE);
+        texture.tex_parameter(gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE);
+        swizzle_gray(gl::TEXTURE_2D_ARRAY, format);
+        texture
+    }
+
+    /// Uploads tightly packed pixels in the format the array was created with.
+    pub fn put_layer(&self, layer: u32, data: &[u8]) {
+        assert!(layer < self.layers, "Layer {} out of range", layer);
+        let row_bytes = self.width * self.format.bytes_per_pixel();
+        assert!(data.len() >= (row_bytes * self.height) as usize,
+                "Not enough pixel data for the texture size");
+        self.bind();
+        unsafe {
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, row_alignment(row_bytes));
+            gl::TexSubImage3D(gl::TEXTURE_2D_ARRAY,
+                              0,
+                              0, 0, layer as i32,
+                              self.width as i32,
+                              self.height as i32,
+                              1,
+                              self.format.format(),
+                              self.format.data_type(),
+                              data.as_ptr() as *const c_void);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+        }
+    }
+
+    /// One layer per image, all of them need the size and color type of the first one.
+    pub fn from_images(images: &[DynamicImage], color_space: ColorSpace) -> Result<Texture2DArray> {
+        let first = images.first()
+                          .ok_or_else(|| Error::ImageSize("a texture array needs at least one layer".to_string()))?;
+        let (width, height) = (first.width(), first.height());
+        let layers = images.iter()
+                           .map(|x| image_data(x, color_space))
+                           .collect::<Vec<_>>();
+        let format = layers[0].1;
+        for (layer, image) in images.iter().enumerate() {
+            if image.width() != width || image.height() != height {
+                return Err(Error::ImageSize(format!("layer {} is {}x{}, expected {}x{}", layer,
+                                                    image.width(), image.height(), width, height)));
+            }
+            if layers[layer].1 != format {
+                return Err(Error::ImageSize(format!("layer {} is {:?}, expected {:?}", layer,
+                                                    layers[layer].1, format)));
+            }
+        }
+
+        let texture = Texture2DArray::new(width, height, images.len() as u32, format);
+        for (layer, (data, _)) in layers.iter().enumerate() {
+            texture.put_layer(layer as u32, data);
+        }
+        texture.tex_parameter(gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR);
+        unsafe {
+            gl::GenerateMipmap(gl::TEXTURE_2D_ARRAY);
+        }
+        Ok(texture)
+    }
+}
+
+impl Drop for Texture2DArray {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.id);
+        }
+    }
+}
+
+impl State for Texture2DArray {
+    fn bind(&self) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.id);
+        }
+    }
+
+    fn unbind(&self) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, 0);
+        }
+    }
+}
+
+/// Faces in gl order: +x, -x, +y, -y, +z, -z.
+/// Face images are not flipped, cubemaps expect the first row at the top.
+pub struct TextureCube {
+    id: u32,
+    /// width and height of every face
+    size: u32,
+}
+
+impl TextureCube {
+    pub fn tex_parameter(&self, parameter: GLenum, state: GLenum) {
+        tex_parameter(gl::TEXTURE_CUBE_MAP, parameter, state);
+    }
+
+    pub fn new(size: u32) -> TextureCube {
+        let mut id = 0;
+        unsafe {
+            gl::GenTextures(1, &mut id);
+            // filter across face edges, it's global state but there is no reason to ever turn it off
+            gl::Enable(gl::TEXTURE_CUBE_MAP_SEAMLESS);
+        }
+        let texture = TextureCube { id, size };
+        texture.bind();
+        texture.tex_parameter(gl::TEXTURE_MIN_FILTER, gl::LINEAR);
+        texture.tex_parameter(gl::TEXTURE_MAG_FILTER, gl::LINEAR);
+        texture.tex_parameter(gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE);
+        texture.tex_parameter(gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE);
+        texture.tex_parameter(gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE);
+        texture
+    }
+
+    pub fn put_face(&self, face: u32, data: &[u8], format: TextureFormat) {
+        assert!(face < 6, "A cubemap has 6 faces, got face {}", face);
+        let row_bytes = self.size * format.bytes_per_pixel();
+        assert!(data.len() >= (row_bytes * self.size) as usize,
+                "Not enough pixel data for the face size");
+        self.bind();
+        unsafe {
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, row_alignment(row_bytes));
+            gl::TexImage2D(gl::TEXTURE_CUBE_MAP_POSITIVE_X + face,
+                           0,
+                           format.internal_format() as i32,
+                           self.size as i32,
+                           self.size as i32,
+                           0,
+                           format.format(),
+                           format.data_type(),
+                           data.as_ptr() as *const c_void);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+        }
+    }
+
+    fn finish(&self) {
+        self.bind();
+        self.tex_parameter(gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR);
+        unsafe {
+            gl::GenerateMipmap(gl::TEXTURE_CUBE_MAP);
+        }
+    }
+
+    /// Six square images of the same size, in face order.
+    pub fn from_images(faces: &[DynamicImage; 6], color_space: ColorSpace) -> Result<TextureCube> {
+        let size = faces[0].width();
+        for (face, image) in faces.iter().enumerate() {
+            if image.width() != size || image.height() != size {
+                return Err(Error::ImageSize(format!("cubemap face {} is {}x{}, expected {}x{}", face,
+                                                    image.width(), image.height(), size, size)));
+            }
+        }
+        let texture = TextureCube::new(size);
+        for (face, image) in faces.iter().enumerate() {
+            let (data, format) = image_data(image, color_space);
+            texture.put_face(face as u32, &data, format);
+        }
+        texture.finish();
+        Ok(texture)
+    }
+
+    /// Projects a latitude/longitude panorama, usually an `.hdr`, onto the six faces.
+    /// The faces are stored as linear half floats, so values above 1 survive.
+    /// An srgb panorama is decoded first, the resampling has to happen on linear values.
+    pub fn from_equirectangular(image: &DynamicImage, size: u32, color_space: ColorSpace) -> TextureCube {
+        let mut panorama = image.to_rgba32f();
+        if color_space == ColorSpace::Srgb {
+            for pixel in panorama.pixels_mut() {
+                let [r, g, b, a] = pixel.0;
+                let linear = srgb_to_linear(Vec3::new(r, g, b));
+                pixel.0 = [linear.x, linear.y, linear.z, a];
+            }
+        }
+        let texture = TextureCube::new(size);
+        let mut data = Vec::with_capacity((size * size * 4) as usize);
+        for face in 0..6 {
+            data.clear();
+            for y in 0..size {
+                for x in 0..size {
+                    let u = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+                    let v = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+                    let color = sample_equirectangular(&panorama, face_direction(face, u, v));
+                    data.extend_from_slice(&color.to_array());
+                }
+            }
+            let bytes: Vec<u8> = data.iter().flat_map(|x| x.to_ne_bytes()).collect();
+            texture.put_face(face, &bytes, TextureFormat::RGBA16F);
+        }
+        texture.finish();
+        texture
+    }
+}
+
+/// Direction through a point of a face, `u` and `v` go from -1 to 1 with `v` pointing down.
+fn face_direction(face: u32, u: f32, v: f32) -> Vec3 {
+    let direction = match face {
+        0 => Vec3::new(1.0, -v, -u),
+        1 => Vec3::new(-1.0, -v, u),
+        2 => Vec3::new(u, 1.0, v),
+        3 => Vec3::new(u, -1.0, -v),
+        4 => Vec3::new(u, -v, 1.0),
+        _ => Vec3::new(-u, -v, -1.0),
+    };
+    direction.normalize()
+}
+
+/// Bilinear lookup, wrapping around horizontally
+fn sample_equirectangular(panorama: &Rgba32FImage, direction: Vec3) -> Vec4 {
+    let (width, height) = panorama.dimensions();
+    let longitude = direction.z.atan2(direction.x);
+    let latitude = direction.y.clamp(-1.0, 1.0).asin();
+    let x = (0.5 + longitude / (2.0 * PI)) * width as f32 - 0.5;
+    let y = (0.5 - latitude / PI) * height as f32 - 0.5;
+
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let texel = |x: f32, y: f32| {
+        let x = (x as i64).rem_euclid(width as i64) as u32;
+        let y = (y as i64).clamp(0, height as i64 - 1) as u32;
+        Vec4::from(panorama.get_pixel(x, y).0)
+    };
+    let top = texel(x0, y0).lerp(texel(x0 + 1.0, y0), fx);
+    let bottom = texel(x0, y0 + 1.0).lerp(texel(x0 + 1.0, y0 + 1.0), fx);
+    top.lerp(bottom, fy)
+}
+
+impl Drop for TextureCube {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.id);
+        }
+    }
+}
+
+impl State for TextureCube {
+    fn bind(&self) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.id);
+        }
+    }
+
+    fn unbind(&self) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+        }
+    }
+}
+
+/// A volume, sampled with `sampler3D`.
+#[allow(dead_code)]
+pub struct Texture3D {
+    id: u32,
+    width: u32,
+    height: u32,
+    depth: u32,
+}
+
+#[allow(dead_code)]
+impl Texture3D {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    pub fn tex_parameter(&self, parameter: GLenum, state: GLenum) {
+        tex_parameter(gl::TEXTURE_3D, parameter, state);
+    }
+
+    pub fn new(width: u32, height: u32, depth: u32) -> Texture3D {
+        let mut id = 0;
+        unsafe {
+            gl::GenTextures(1, &mut id);
+        }
+        let texture = Texture3D {
+            id,
+            width,
+            height,
+            depth,
+        };
+        texture.bind();
+        texture.tex_parameter(gl::TEXTURE_MIN_FILTER, gl::LINEAR);
+        texture.tex_parameter(gl::TEXTURE_MAG_FILTER, gl::LINEAR);
+        texture.tex_parameter(gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE);
+        texture.tex_parameter(gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE);
+        texture.tex_parameter(gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE);
+        texture
+    }
+
+    /// Tightly packed slices, front to back. An empty slice only allocates the storage.
+    pub fn put_pixels(&self, data: &[u8], format: TextureFormat) {
+        let row_bytes = self.width * format.bytes_per_pixel();
+        let pointer = if data.is_empty() {
+            std::ptr::null()
+        } else {
+            assert!(data.len() >= (row_bytes * self.height * self.depth) as usize,
+                    "Not enough pixel data for the texture size");
+            data.as_ptr() as *const c_void
+        };
+        self.bind();
+        unsafe {
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, row_alignment(row_bytes));
+            gl::TexImage3D(gl::TEXTURE_3D,
+                           0,
+                           format.internal_format() as i32,
+                           self.width as i32,
+                           self.height as i32,
+                           self.depth as i32,
+                           0,
+                           format.format(),
+                           format.data_type(),
+                           pointer);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+        }
+        swizzle_gray(gl::TEXTURE_3D, format);
+    }
+
+    pub fn from_pixels(data: &[u8], width: u32, height: u32, depth: u32,
+                       format: TextureFormat) -> Texture3D {
+        let texture = Texture3D::new(width, height, depth);
+        texture.put_pixels(data, format);
+        texture
+    }
+}
+
+impl Drop for Texture3D {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.id);
+        }
+    }
+}
+
+impl State for Texture3D {
+    fn bind(&self) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_3D, self.id);
+        }
+    }
+
+    fn unbind(&self) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_3D, 0);
+        }
+    }
+}
+
+/// Render target storage for msaa, can't be sampled with filtering,
+/// either `texelFetch` it with `sampler2DMS` or resolve it with a blit.
+#[allow(dead_code)]
+pub struct Texture2DMultisample {
+    id: u32,
+    width: u32,
+    height: u32,
+    samples: u32,
+}
+
+#[allow(dead_code)]
+impl Texture2DMultisample {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    pub fn new(width: u32, height: u32, samples: u32, format: TextureFormat) -> Texture2DMultisample {
+        let mut id = 0;
+        unsafe {
+            gl::GenTextures(1, &mut id);
+        }
+        let texture = Texture2DMultisample {
+            id,
+            width,
+            height,
+            samples,
+        };
+        texture.bind();
+        unsafe {
+            gl::TexImage2DMultisample(gl::TEXTURE_2D_MULTISAMPLE,
+                                      samples as i32,
+                                      format.internal_format(),
+                                      width as i32,
+                                      height as i32,
+                                      gl::TRUE);
+        }
+        texture
+    }
+}
+
+impl Drop for Texture2DMultisample {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.id);
+        }
+    }
+}
+
+impl State for Texture2DMultisample {
+    fn bind(&self) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D_MULTISAMPLE, self.id);
+        }
+    }
+
+    fn unbind(&self) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D_MULTISAMPLE, 0);
+        }
+    }
 }
 
 pub trait State {
     fn bind(&self);
     fn unbind(&self);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::HeadlessContext;
+
+    fn rgb(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::new_rgb8(width, height)
+    }
+
+    #[test]
+    fn mismatched_images_are_errors() {
+        // checked before anything touches gl, no context needed
+        let mut faces = [rgb(4, 4), rgb(4, 4), rgb(4, 4), rgb(4, 4), rgb(4, 4), rgb(4, 4)];
+        faces[3] = rgb(4, 2);
+        assert!(matches!(TextureCube::from_images(&faces, ColorSpace::Srgb), Err(Error::ImageSize(_))));
+        let faces = [rgb(4, 2), rgb(4, 2), rgb(4, 2), rgb(4, 2), rgb(4, 2), rgb(4, 2)];
+        assert!(matches!(TextureCube::from_images(&faces, ColorSpace::Srgb), Err(Error::ImageSize(_))));
+
+        let layers = [rgb(4, 4), rgb(8, 4)];
+        assert!(matches!(Texture2DArray::from_images(&layers, ColorSpace::Srgb), Err(Error::ImageSize(_))));
+        let layers = [rgb(4, 4), DynamicImage::new_rgba8(4, 4)];
+        assert!(matches!(Texture2DArray::from_images(&layers, ColorSpace::Srgb), Err(Error::ImageSize(_))));
+        assert!(matches!(Texture2DArray::from_images(&[], ColorSpace::Srgb), Err(Error::ImageSize(_))));
+    }
+
+    #[test]
+    fn every_texture_type_allocates() {
+        let _context = HeadlessContext::new()
+            .unwrap_or_else(|e| panic!("needs a surfaceless EGL context: {}", e));
+        let no_error = || unsafe { gl::GetError() } == gl::NO_ERROR;
+
+        let array = Texture2DArray::from_images(&[rgb(4, 4), rgb(4, 4), rgb(4, 4)], ColorSpace::Srgb).unwrap();
+        assert_eq!((array.width(), array.height(), array.layers()), (4, 4, 3));
+        let shadows = Texture2DArray::new(8, 8, 4, TextureFormat::Depth32F);
+        shadows.put_layer(2, &vec![0u8; 8 * 8 * 4]);
+        assert!(no_error());
+
+        let volume = Texture3D::from_pixels(&vec![0u8; 4 * 4 * 4 * 4], 4, 4, 4, TextureFormat::RGBA8);
+        assert_eq!(volume.depth(), 4);
+        let empty = Texture3D::new(2, 2, 2);
+        empty.put_pixels(&[], TextureFormat::R8);
+        assert!(no_error());
+
+        let multisample = Texture2DMultisample::new(16, 16, 4, TextureFormat::RGBA16F);
+        let mut samples = 0;
+        unsafe {
+            gl::GetTexLevelParameteriv(gl::TEXTURE_2D_MULTISAMPLE, 0, gl::TEXTURE_SAMPLES, &mut samples);
+        }
+        assert!(samples >= multisample.samples() as i32);
+        assert!(no_error());
+
+        let cube = TextureCube::from_images(&[rgb(2, 2), rgb(2, 2), rgb(2, 2), rgb(2, 2), rgb(2, 2), rgb(2, 2)],
+                                            ColorSpace::Linear).unwrap();
+        cube.bind();
+        cube.unbind();
+        assert!(no_error());
+    }
+}
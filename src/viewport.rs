@@ -0,0 +1,178 @@
+use glam::{UVec2, Vec2};
+
+use crate::camera::Camera;
+use crate::geometry::Ray;
+
+/// How the scene image is placed in the viewport panel.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Fit {
+    /// the scene target takes the whole panel
+    Fill,
+    /// a fixed aspect ratio, centered with bars on the sides that don't fit
+    Letterbox(f32),
+}
+
+/// The rectangle of the panel the scene image is drawn to and the size of the
+/// scene target behind it. Positions are in window coordinates like the mouse cursor,
+/// pixels are scene target pixels with the origin at the bottom left like gl.
+pub struct Viewport {
+    pub fit: Fit,
+    position: Vec2,
+    size: Vec2,
+    target_size: UVec2,
+}
+
+impl Viewport {
+    pub fn new(target_width: u32, target_height: u32) -> Viewport {
+        Viewport {
+            fit: Fit::Fill,
+            position: Vec2::ZERO,
+            size: Vec2::ZERO,
+            target_size: UVec2::new(target_width, target_height),
+        }
+    }
+
+    /// Places the image inside the content region of the panel.
+    /// `scale` is framebuffer pixels per window coordinate, more than 1 on high dpi displays.
+    /// Returns the target size that gives one target pixel per framebuffer pixel.
+    pub fn layout(&mut self, region_position: [f32; 2], region_size: [f32; 2], scale: Vec2) -> UVec2 {
+        let region = Vec2::from(region_size).max(Vec2::ONE);
+        let size = match self.fit {
+            Fit::Fill => region,
+            Fit::Letterbox(aspect) => {
+                if region.x / region.y > aspect {
+                    Vec2::new(region.y * aspect, region.y)
+                } else {
+                    Vec2::new(region.x, region.x / aspect)
+                }
+            }
+        };
+        self.position = Vec2::from(region_position) + (region - size) * 0.5;
+        self.size = size.floor();
+        (self.size * scale).round().max(Vec2::ONE).as_uvec2()
+    }
+
+    pub fn position(&self) -> [f32; 2] {
+        self.position.to_array()
+    }
+
+    pub fn size(&self) -> [f32; 2] {
+        self.size.to_array()
+    }
+
+    /// Has to be updated whenever the scene target is actually resized,
+    /// the layout only asks for a size.
+    pub fn set_target_size(&mut self, width: u32, height: u32) {
+        self.target_size = UVec2::new(width, height);
+    }
+
+    pub fn target_size(&self) -> UVec2 {
+        self.target_size
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.target_size.x as f32 / self.target_size.y.max(1) as f32
+    }
+
+    /// Normalized device coordinates of a window position, `None` outside the image.
+    pub fn to_ndc(&self, cursor: Vec2) -> Option<Vec2> {
+        if self.size.x <= 0.0 || self.size.y <= 0.0 {
+            return None;
+        }
+        let relative = (cursor - self.position) / self.size;
+        if relative.x < 0.0 || relative.y < 0.0 || relative.x >= 1.0 || relative.y >= 1.0 {
+            return None;
+        }
+        Some(Vec2::new(relative.x * 2.0 - 1.0, 1.0 - relative.y * 2.0))
+    }
+
+    /// Scene target pixel under a window position, `None` outside the image.
+    pub fn to_pixel(&self, cursor: Vec2) -> Option<UVec2> {
+        let ndc = self.to_ndc(cursor)?;
+        let pixel = (ndc * 0.5 + 0.5) * self.target_size.as_vec2();
+        Some(pixel.floor().as_uvec2().min(self.target_size - UVec2::ONE))
+    }
+
+    /// World space ray through a window position, `None` outside the image.
+    pub fn ray(&self, camera: &Camera, cursor: Vec2) -> Option<Ray> {
+        let ndc = self.to_ndc(cursor)?;
+        Some(camera.ray(self.aspect(), ndc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A panel right of a 200 wide settings column, like the ui lays it out
+    const REGION_POSITION: [f32; 2] = [200.0, 0.0];
+
+    fn laid_out(fit: Fit, region_size: [f32; 2], scale: f32) -> Viewport {
+        let mut viewport = Viewport::new(1, 1);
+        viewport.fit = fit;
+        let target = viewport.layout(REGION_POSITION, region_size, Vec2::splat(scale));
+        viewport.set_target_size(target.x, target.y);
+        viewport
+    }
+
+    #[test]
+    fn fill_takes_the_region() {
+        let viewport = laid_out(Fit::Fill, [800.0, 400.0], 2.0);
+        assert_eq!(viewport.position(), REGION_POSITION);
+        assert_eq!(viewport.size(), [800.0, 400.0]);
+        assert_eq!(viewport.target_size(), UVec2::new(1600, 800));
+        assert_eq!(viewport.aspect(), 2.0);
+    }
+
+    #[test]
+    fn letterbox_offsets() {
+        // too wide, bars left and right
+        let viewport = laid_out(Fit::Letterbox(1.0), [800.0, 400.0], 1.0);
+        assert_eq!(viewport.position(), [400.0, 0.0]);
+        assert_eq!(viewport.size(), [400.0, 400.0]);
+        assert_eq!(viewport.target_size(), UVec2::new(400, 400));
+
+        // too tall, bars above and below
+        let viewport = laid_out(Fit::Letterbox(16.0 / 9.0), [400.0, 800.0], 1.0);
+        assert_eq!(viewport.position(), [200.0, 287.5]);
+        assert_eq!(viewport.size(), [400.0, 225.0]);
+        assert_eq!(viewport.target_size(), UVec2::new(400, 225));
+
+        // the bars are not part of the image
+        assert_eq!(viewport.to_ndc(Vec2::new(300.0, 100.0)), None);
+        assert_eq!(viewport.to_pixel(Vec2::new(300.0, 700.0)), None);
+        assert_eq!(viewport.to_pixel(Vec2::new(199.0, 400.0)), None);
+    }
+
+    #[test]
+    fn ndc_and_pixels() {
+        let viewport = laid_out(Fit::Letterbox(1.0), [800.0, 400.0], 2.0);
+        assert_eq!(viewport.to_ndc(Vec2::new(400.0, 0.0)), Some(Vec2::new(-1.0, 1.0)));
+        assert_eq!(viewport.to_ndc(Vec2::new(600.0, 200.0)), Some(Vec2::ZERO));
+
+        // window y goes down, pixels start at the bottom left like gl
+        assert_eq!(viewport.to_pixel(Vec2::new(400.0, 399.9)), Some(UVec2::new(0, 0)));
+        assert_eq!(viewport.to_pixel(Vec2::new(400.0, 0.0)), Some(UVec2::new(0, 799)));
+        assert_eq!(viewport.to_pixel(Vec2::new(799.9, 0.0)), Some(UVec2::new(799, 799)));
+        assert_eq!(viewport.to_pixel(Vec2::new(800.0, 0.0)), None);
+        assert_eq!(viewport.to_pixel(Vec2::new(600.0, 200.0)), Some(UVec2::new(400, 400)));
+    }
+
+    #[test]
+    fn rays_match_the_image() {
+        let camera = Camera::new(60.0, 1.0);
+        let viewport = laid_out(Fit::Letterbox(1.0), [800.0, 400.0], 1.0);
+        let center = viewport.ray(&camera, Vec2::new(600.0, 200.0)).unwrap();
+        // the view looks along -front, see `Camera::frame`
+        assert!(center.direction.abs_diff_eq(-camera.front(), 1e-4), "{}", center.direction);
+
+        // square image, so the edges are the same angle away horizontally and vertically
+        let right = viewport.ray(&camera, Vec2::new(799.9, 200.0)).unwrap();
+        let top = viewport.ray(&camera, Vec2::new(600.0, 0.0)).unwrap();
+        let horizontal = center.direction.angle_between(right.direction);
+        let vertical = center.direction.angle_between(top.direction);
+        assert!((horizontal - 30f32.to_radians()).abs() < 1e-3, "{}", horizontal.to_degrees());
+        assert!((vertical - 30f32.to_radians()).abs() < 1e-3, "{}", vertical.to_degrees());
+        assert!(viewport.ray(&camera, Vec2::new(300.0, 200.0)).is_none());
+    }
+}